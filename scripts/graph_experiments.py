@@ -14,14 +14,32 @@ GENOME_SIZES = [200000, 400000, 600000, 800000, 1000000, 5000000, 10000000, 5000
 HG38_SIZES = [1000, 10000, 100000, 1000000]
 
 """
-sais genome iterations
-naive-sa genome iterations
-otable genome iterations skips
-approx genome reads iterations skips
-exact genome reads iterations skips
+bench sais --genome genome --iterations iterations
+bench naive-sa --genome genome --iterations iterations
+bench otable --genome genome --iterations iterations --spacing skips
+bench approx --genome genome --reads reads --iterations iterations --spacing skips --edits edits
+bench exact-bwt --genome genome --reads reads --iterations iterations --spacing skips
+bench exact-bwt --genome genome --reads reads --iterations iterations --spacing skips --layout layout
+bench exact-bwt --genome genome --reads reads --iterations iterations --rank rank
 """
 
 
+def bench_command(type, genome, iterations, reads=None, spacing=None, edits=None, layout=None, rank=None):
+    command = f"{EXE_PATH} bench {type} --genome {os.path.join('resources', 'genomes', genome + '.fa')}"
+    if reads is not None:
+        command += f" --reads {os.path.join('resources', 'reads', reads + '.fq')}"
+    command += f" --iterations {iterations}"
+    if spacing is not None:
+        command += f" --spacing {spacing}"
+    if edits is not None:
+        command += f" --edits {edits}"
+    if layout is not None:
+        command += f" --layout {layout}"
+    if rank is not None:
+        command += f" --rank {rank}"
+    return command + " --no-output"
+
+
 def main():
     initialize()
     # ex1()
@@ -35,6 +53,8 @@ def main():
     # ex11()
     ex12()
     ex13()
+    ex14()
+    ex15()
 
 
 def ex1():
@@ -181,14 +201,40 @@ def ex13():
     g(0, 10, 20, 30, 40, 50)
 
 
+def ex14():
+    """Eksperiment 14
+
+    Hvor meget hurtigere er access med interleaved O-table layout end med separate?
+
+    Køres på:
+    - main
+    """
+    for layout in ["separate", "interleaved"]:
+        graph_variable_genome(f"ex14-approx-{layout}", "approx", 50, reads=READS_S, spacing=64, edits=1, layout=layout)
+        graph_variable_genome(f"ex14-exact-bwt-{layout}", "exact-bwt", 500, reads=READS_L, spacing=64, layout=layout)
+        graph_variable_genome_hg38(f"ex14-hg38-approx-{layout}", "approx", 50, reads=READS_S, spacing=64, edits=1, layout=layout)
+        graph_variable_genome_hg38(f"ex14-hg38-exact-bwt-{layout}", "exact-bwt", 500, reads=READS_L, spacing=64, layout=layout)
+
+
+def ex15():
+    """Eksperiment 15
+
+    Hvor meget langsommere er søgning over et wavelet tree end over O-tabellen, som fylder mere?
+
+    Køres på:
+    - main
+    """
+    for rank in ["o-table", "wavelet-tree"]:
+        graph_variable_genome(f"ex15-approx-{rank}", "approx", 50, reads=READS_S, edits=1, rank=rank)
+        graph_variable_genome(f"ex15-exact-bwt-{rank}", "exact-bwt", 500, reads=READS_L, rank=rank)
+
+
 def initialize():
     build = "cargo build --release --quiet"
     os.popen(build).read()
 
 
-def graph_variable_spacing(fname, type, genome, iterations, reads=None):
-    reads = "" if reads is None else f"{reads} "
-
+def graph_variable_spacing(fname, type, genome, iterations, reads=None, edits=None, layout=None, rank=None):
     print(f"Computing {fname}...")
     git_branch = os.popen("git branch --show-current").read().strip()
 
@@ -204,7 +250,7 @@ def graph_variable_spacing(fname, type, genome, iterations, reads=None):
     for spacing in SPACINGS:
         print("\u2588" * 3, end="", flush=True)
 
-        res = os.popen(f"{EXE_PATH} {type} {genome} {reads}{iterations} {spacing} --no-output").read()
+        res = os.popen(bench_command(type, genome, iterations, reads, spacing, edits, layout, rank)).read()
         average_ns = int(res)
 
         data.append({
@@ -236,12 +282,7 @@ def graph_variable_spacing(fname, type, genome, iterations, reads=None):
     plt.close()
 
 
-def graph_variable_genome(fname, type, iterations, reads=None, spacing=None, edits=None):
-    iterations = "" if iterations is None else f"{iterations} "
-    reads = "" if reads is None else f"{reads} "
-    spacing = "" if spacing is None else f"{spacing} "
-    edits = "" if edits is None else f"{edits}"
-
+def graph_variable_genome(fname, type, iterations, reads=None, spacing=None, edits=None, layout=None, rank=None):
     print(f"Computing {fname}...")
     git_branch = os.popen("git branch --show-current").read().strip()
 
@@ -259,7 +300,7 @@ def graph_variable_genome(fname, type, iterations, reads=None, spacing=None, edi
 
         genome = f"rand-{genome_size}"
 
-        res = os.popen(f"{EXE_PATH} {type} {genome} {reads}{iterations}{spacing}{edits} --no-output").read()
+        res = os.popen(bench_command(type, genome, iterations, reads, spacing, edits, layout, rank)).read()
         average_ns = int(res)
 
         data.append({
@@ -291,12 +332,7 @@ def graph_variable_genome(fname, type, iterations, reads=None, spacing=None, edi
     plt.close()
 
 
-def graph_variable_genome_hg38(fname, type, iterations, reads=None, spacing=None, edits=None):
-    iterations = "" if iterations is None else f"{iterations} "
-    reads = "" if reads is None else f"{reads} "
-    spacing = "" if spacing is None else f"{spacing} "
-    edits = "" if edits is None else f"{edits}"
-
+def graph_variable_genome_hg38(fname, type, iterations, reads=None, spacing=None, edits=None, layout=None, rank=None):
     print(f"Computing {fname}...")
     git_branch = os.popen("git branch --show-current").read().strip()
 
@@ -314,7 +350,7 @@ def graph_variable_genome_hg38(fname, type, iterations, reads=None, spacing=None
 
         genome = f"hg38-{genome_size}"
 
-        res = os.popen(f"{EXE_PATH} {type} {genome} {reads}{iterations}{spacing}{edits} --no-output").read()
+        res = os.popen(bench_command(type, genome, iterations, reads, spacing, edits, layout, rank)).read()
         average_ns = int(res)
 
         data.append({
@@ -0,0 +1,335 @@
+use crate::approx_search::{match_cost, EditModel};
+use crate::cigar::{Cigar, CigarOp};
+use crate::error::{GeneSearchError, Result};
+use crate::hits::Hit;
+use crate::util::{reverse_complement, Strand};
+use crate::ALPHABET;
+use std::fmt::{Display, Formatter};
+
+/// A hit laid out against the reference it aligned to, as three rows: the
+/// reference, a bar under every matched base, and the read as it was aligned.
+/// Gaps are written as `-`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlignmentView {
+    pub reference: String,
+    pub bars: String,
+    pub read: String,
+}
+
+impl AlignmentView {
+    /// Lays out a hit of a remapped read against the remapped genome it was
+    /// searched in. Reverse strand hits show the reverse complemented read.
+    ///
+    /// Fails if the CIGAR does not fit the read or runs off the genome.
+    pub fn new(genome: &[u8], read: &[u8], hit: &Hit) -> Result<Self> {
+        let query = aligned_query(read, hit.strand);
+        let reference = covered_reference(genome, &query, hit)?;
+
+        let mut view = AlignmentView {
+            reference: String::new(),
+            bars: String::new(),
+            read: String::new(),
+        };
+        let (mut r, mut q) = (0, 0);
+        for op in hit.cigar.ops() {
+            let (reference_char, bar, read_char) = match op {
+                CigarOp::Match => (symbol(reference[r]), '|', symbol(query[q])),
+                CigarOp::Mismatch => (symbol(reference[r]), ' ', symbol(query[q])),
+                CigarOp::Insertion => ('-', ' ', symbol(query[q])),
+                CigarOp::Deletion => (symbol(reference[r]), ' ', '-'),
+                CigarOp::SoftClip => (' ', ' ', symbol(query[q])),
+            };
+            view.reference.push(reference_char);
+            view.bars.push(bar);
+            view.read.push(read_char);
+            r += op.consumes_reference() as usize;
+            q += op.consumes_query() as usize;
+        }
+        Ok(view)
+    }
+}
+
+impl Display for AlignmentView {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}\n{}\n{}", self.reference, self.bars, self.read)
+    }
+}
+
+/// The read as the search aligned it, reverse complemented for the reverse strand.
+fn aligned_query(read: &[u8], strand: Strand) -> Vec<u8> {
+    match strand {
+        Strand::Forward => read.to_vec(),
+        Strand::Reverse => reverse_complement(read),
+    }
+}
+
+/// The genome bases covered by a hit, checking that its CIGAR fits the query.
+fn covered_reference<'a>(genome: &'a [u8], query: &[u8], hit: &Hit) -> Result<&'a [u8]> {
+    if hit.cigar.query_len() != query.len() {
+        return Err(GeneSearchError::Verification(format!(
+            "CIGAR {} covers {} read bases, but the read has {}",
+            hit.cigar,
+            hit.cigar.query_len(),
+            query.len()
+        )));
+    }
+    let end = hit.position + hit.cigar.reference_span();
+    genome.get(hit.position..end).ok_or_else(|| {
+        GeneSearchError::Verification(format!(
+            "CIGAR {} at {} runs past the end of the genome",
+            hit.cigar, hit.position
+        ))
+    })
+}
+
+fn symbol(s: u8) -> char {
+    match s {
+        1..=4 => ALPHABET[s as usize],
+        _ => 'N',
+    }
+}
+
+/// The edits of aligning a query to a reference substring along a CIGAR, with
+/// wildcards costing `wildcard_cost`. Fails if an operation marked as a match
+/// aligns bases at a cost, or one marked as a mismatch aligns them for free.
+pub fn cigar_cost(
+    reference: &[u8],
+    query: &[u8],
+    cigar: &Cigar,
+    wildcard_cost: usize,
+) -> Result<usize> {
+    let (mut r, mut q, mut cost) = (0, 0, 0);
+    for op in cigar.ops() {
+        match op {
+            CigarOp::Match | CigarOp::Mismatch => {
+                let op_cost = match_cost(reference[r], query[q], wildcard_cost) as usize;
+                if (op_cost == 0) != (op == CigarOp::Match) {
+                    return Err(GeneSearchError::Verification(format!(
+                        "CIGAR {} aligns {} to {} as {}",
+                        cigar,
+                        symbol(query[q]),
+                        symbol(reference[r]),
+                        op.sam_char()
+                    )));
+                }
+                cost += op_cost;
+            }
+            CigarOp::Insertion | CigarOp::Deletion => cost += 1,
+            CigarOp::SoftClip => {}
+        }
+        r += op.consumes_reference() as usize;
+        q += op.consumes_query() as usize;
+    }
+    Ok(cost)
+}
+
+/// The fewest edits aligning all of the query to all of the reference substring,
+/// with a plain dynamic programming table. As in the search, the alignment may
+/// not start or end with a deletion, since that would make it a hit elsewhere.
+/// Under Hamming distance, substrings of another length have no alignment.
+pub fn edit_distance(
+    reference: &[u8],
+    query: &[u8],
+    edit_model: EditModel,
+    wildcard_cost: usize,
+) -> Option<usize> {
+    let cost = |r: u8, q: u8| match_cost(r, q, wildcard_cost) as usize;
+    if edit_model == EditModel::Hamming {
+        if reference.len() != query.len() {
+            return None;
+        }
+        return Some(reference.iter().zip(query).map(|(&r, &q)| cost(r, q)).sum());
+    }
+
+    let (n, m) = (query.len(), reference.len());
+    let unreachable = usize::MAX / 2;
+    // Row i holds the edits of aligning query[..i] to each reference prefix. No
+    // reference base can be deleted before the first query base is aligned.
+    let mut previous: Vec<usize> = (0..=m)
+        .map(|j| if j == 0 { 0 } else { unreachable })
+        .collect();
+    for i in 1..=n {
+        let mut current = vec![unreachable; m + 1];
+        current[0] = i;
+        for j in 1..=m {
+            let substitute = previous[j - 1] + cost(reference[j - 1], query[i - 1]);
+            let insert = previous[j] + 1;
+            let delete = if i == n && j == m {
+                unreachable
+            } else {
+                current[j - 1] + 1
+            };
+            current[j] = substitute.min(insert).min(delete);
+        }
+        previous = current;
+    }
+    Some(previous[m]).filter(|&edits| edits < unreachable)
+}
+
+/// Checks a hit of a remapped read against the remapped genome it was searched
+/// in: its CIGAR must fit the read and the covered reference, must cost the
+/// reported edits, and no alignment of the read to that substring may cost fewer.
+pub fn verify_hit(
+    genome: &[u8],
+    read: &[u8],
+    hit: &Hit,
+    edit_model: EditModel,
+    wildcard_cost: usize,
+) -> Result<()> {
+    let query = aligned_query(read, hit.strand);
+    let reference = covered_reference(genome, &query, hit)?;
+    let failed = |message: String| {
+        let view = AlignmentView::new(genome, read, hit)
+            .map(|view| format!("\n{}", view))
+            .unwrap_or_default();
+        Err(GeneSearchError::Verification(format!(
+            "{} at {}: {}{}",
+            hit.cigar, hit.position, message, view
+        )))
+    };
+
+    let cost = cigar_cost(reference, &query, &hit.cigar, wildcard_cost)?;
+    if cost != hit.edits {
+        return failed(format!(
+            "costs {} edits, but {} were reported",
+            cost, hit.edits
+        ));
+    }
+    match edit_distance(reference, &query, edit_model, wildcard_cost) {
+        Some(distance) if distance == hit.edits => Ok(()),
+        Some(distance) => failed(format!(
+            "{} edits were reported, but the read aligns with {}",
+            hit.edits, distance
+        )),
+        None => failed("the read cannot align to the covered reference".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        approx_search::{approx_search_strands, ApproxSearchParams},
+        hits::hits_from_approx,
+        o_table::OTable,
+        sais::suffix_array_induced_sort,
+        table_gen::generate_c_table,
+        util::{remap_query, remap_reference},
+    };
+
+    fn hit(strand: Strand, position: usize, edits: usize, cigar: &str) -> Hit {
+        Hit {
+            strand,
+            position,
+            edits,
+            penalty: edits,
+            cigar: cigar.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_view() {
+        let genome = remap_reference("TACGT").unwrap();
+        let read = remap_query("TAGT").unwrap();
+        let view =
+            AlignmentView::new(&genome, &read, &hit(Strand::Forward, 0, 1, "2=1D2=")).unwrap();
+        assert_eq!(view.to_string(), "TACGT\n|| ||\nTA-GT");
+
+        // The reverse complement of ACNTAG, with a wildcard that costs an edit
+        let genome = remap_reference("GGCTAAGTT").unwrap();
+        let read = remap_query("ACNTAG").unwrap();
+        let view =
+            AlignmentView::new(&genome, &read, &hit(Strand::Reverse, 2, 1, "3=1X2=")).unwrap();
+        assert_eq!(view.to_string(), "CTAAGT\n||| ||\nCTANGT");
+    }
+
+    #[test]
+    fn test_edit_distance_keeps_deletions_inside() {
+        let reference = remap_query("TACGT").unwrap();
+        let query = remap_query("ACGT").unwrap();
+        // Deleting the leading T would cost 1, but that alignment starts at the A
+        assert_eq!(
+            edit_distance(&reference, &query, EditModel::Levenshtein, 1),
+            Some(2)
+        );
+        assert_eq!(
+            edit_distance(
+                &remap_query("ACGGT").unwrap(),
+                &query,
+                EditModel::Levenshtein,
+                1
+            ),
+            Some(1)
+        );
+        assert_eq!(
+            edit_distance(&reference, &query, EditModel::Hamming, 1),
+            None
+        );
+        assert_eq!(
+            edit_distance(
+                &remap_query("ANGA").unwrap(),
+                &remap_query("ACGT").unwrap(),
+                EditModel::Hamming,
+                1
+            ),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn test_verify_catches_wrong_hits() {
+        let genome = remap_reference("TACGTTACGA").unwrap();
+        let read = remap_query("ACGT").unwrap();
+        let verify = |hit: &Hit| verify_hit(&genome, &read, hit, EditModel::Levenshtein, 1);
+
+        assert!(verify(&hit(Strand::Forward, 1, 0, "4=")).is_ok());
+        // Wrong number of edits, a mismatch marked as a match, fewer edits possible
+        assert!(verify(&hit(Strand::Forward, 1, 1, "4=")).is_err());
+        assert!(verify(&hit(Strand::Forward, 6, 0, "4=")).is_err());
+        assert!(verify(&hit(Strand::Forward, 6, 2, "3=1I")).is_err());
+        // A CIGAR that does not fit the read or the genome
+        assert!(verify(&hit(Strand::Forward, 1, 0, "3=")).is_err());
+        assert!(verify(&hit(Strand::Forward, 8, 1, "3=1I")).is_err());
+    }
+
+    #[test]
+    fn test_every_search_hit_verifies() {
+        let genome =
+            remap_reference("ACGTNNATCGTGACGGGCTATAGCGANNNTGTCGATGCAATTGGCCATGCAGTNACGTTAGCATCGAT")
+                .unwrap();
+        let mut reverse_genome = genome[..genome.len() - 1].to_vec();
+        reverse_genome.reverse();
+        reverse_genome.push(0);
+        let sa = suffix_array_induced_sort(&genome);
+        let reverse_sa = suffix_array_induced_sort(&reverse_genome);
+        let o_table = OTable::new(&genome, &sa, 4);
+        let reverse_o_table = OTable::new(&reverse_genome, &reverse_sa, 4);
+        let c_table = generate_c_table(&genome);
+
+        for read in ["GATGCAAT", "TCGATNCAAT", "CTATAGGCGA", "ATCGCTAA"] {
+            let read = remap_query(read).unwrap();
+            for edit_model in [EditModel::Levenshtein, EditModel::Hamming] {
+                for wildcard_cost in 0..2 {
+                    let params = ApproxSearchParams {
+                        reference: &genome,
+                        query: &read,
+                        o_table: &o_table,
+                        c_table: &c_table,
+                        rev_o_table: &reverse_o_table,
+                        edits: 2,
+                        edit_model,
+                        wildcard_cost,
+                        scoring: None,
+                    };
+                    let hits =
+                        hits_from_approx(&approx_search_strands(params, true), &sa, &read, None);
+                    for hit in &hits {
+                        if let Err(e) = verify_hit(&genome, &read, hit, edit_model, wildcard_cost) {
+                            panic!("{:?}: {}", hit, e);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
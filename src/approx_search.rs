@@ -1,111 +1,246 @@
+use crate::cigar::CigarOp;
 use crate::o_table::OTable;
-use crate::types::CTable;
-use crate::DTable;
+use crate::quality::QualityScoring;
+use crate::rank::Rank;
+use crate::types::{Alignments, CTable, DTable, StrandAlignments};
+use crate::util::{reverse_complement, Strand, WILDCARD};
 use crate::ALPHABET;
 use std::collections::HashSet;
 use std::convert::TryFrom;
 
-#[derive(Copy, Clone)]
-pub struct ApproxSearchParams<'a> {
+/// The operations an alignment may use, each costing one edit.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EditModel {
+    /// Substitutions, insertions and deletions
+    Levenshtein,
+    /// Substitutions only, so the read aligns to a string of its own length
+    Hamming,
+}
+
+/// The query and tables of an approximate search, over any `Rank` structure of
+/// the forward and reversed reference.
+pub struct ApproxSearchParams<'a, R: ?Sized = OTable> {
     pub reference: &'a [u8],
     pub query: &'a [u8],
-    pub o_table: &'a OTable<'a>,
+    pub o_table: &'a R,
     pub c_table: &'a CTable,
-    pub rev_o_table: &'a OTable<'a>,
+    pub rev_o_table: &'a R,
     pub edits: usize,
+    pub edit_model: EditModel,
+    /// Edit cost of aligning an N (or other ambiguity code) in the query to any base
+    pub wildcard_cost: usize,
+    /// Quality-aware penalties, pruning alignments over their `max_penalty`
+    pub scoring: Option<QualityScoring<'a>>,
+}
+
+// Derived impls would require `R: Copy`, though only references to it are held
+impl<R: ?Sized> Clone for ApproxSearchParams<'_, R> {
+    fn clone(&self) -> Self {
+        *self
+    }
 }
 
+impl<R: ?Sized> Copy for ApproxSearchParams<'_, R> {}
+
 /// Approximative search
-pub fn approx_search(params: ApproxSearchParams) -> HashSet<(usize, usize, String, usize)> {
-    let d_table = calculate_d_table(
-        params.reference,
-        params.query,
-        params.c_table,
-        params.rev_o_table,
-    );
-    let o_table = params.o_table;
-    let c_table = params.c_table;
-
-    let inner_params = InexactRecursionParams {
-        query: params.query,
-        d_table: &d_table,
-        o_table,
-        c_table,
-    };
+pub fn approx_search<R: Rank + ?Sized>(params: ApproxSearchParams<R>) -> Alignments {
+    approx_search_with_stats(params).0
+}
+
+/// Counters of a backtracking or search scheme search, for profiling.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SearchStats {
+    /// Nodes of the search tree taken off the stack
+    pub visited: usize,
+    /// Visited nodes cut off by the lower bound of the D-table, or by the
+    /// penalty limit of a quality-aware search
+    pub pruned: usize,
+    /// Most nodes on the stack at once, or the deepest recursion of a search
+    /// scheme
+    pub max_stack: usize,
+}
 
-    let left = 0;
-    let right = params.reference.len();
-    let i = (params.query.len() - 1) as i32;
+impl SearchStats {
+    /// Adds the counters of another search, keeping the larger stack.
+    pub fn merge(&mut self, other: SearchStats) {
+        self.visited += other.visited;
+        self.pruned += other.pruned;
+        self.max_stack = self.max_stack.max(other.max_stack);
+    }
+}
 
-    let mut result = HashSet::new();
+/// Approximative search, also returning how much of the search tree it visited.
+///
+/// The tree is walked depth first from an explicit stack of nodes, so long reads
+/// do not grow the call stack. Each node only records the operation that reached
+/// it and how many came before, so the CIGAR of the current path is kept in a
+/// single buffer that is cut back whenever the search backtracks.
+pub fn approx_search_with_stats<R: Rank + ?Sized>(
+    params: ApproxSearchParams<R>,
+) -> (Alignments, SearchStats) {
+    // An empty query has no bases to align, so it has no hits either
+    if params.query.is_empty() {
+        return (HashSet::new(), SearchStats::default());
+    }
 
-    // M-operations
-    for (a, symbol_index) in c_table.iter().enumerate().skip(1) {
-        let new_left = symbol_index + o_table.get(a as u8, left);
-        let new_right = symbol_index + o_table.get(a as u8, right);
+    let d_table = match params.edit_model {
+        EditModel::Levenshtein => calculate_d_table(
+            params.query,
+            params.c_table,
+            params.rev_o_table,
+            params.wildcard_cost,
+        ),
+        EditModel::Hamming => calculate_hamming_d_table(
+            params.query,
+            params.o_table,
+            params.rev_o_table,
+            params.c_table,
+            params.wildcard_cost,
+        ),
+    };
+    let search = Backtracking {
+        query: params.query,
+        o_table: params.o_table,
+        c_table: params.c_table,
+        edit_model: params.edit_model,
+        wildcard_cost: params.wildcard_cost,
+        scoring: params.scoring,
+    };
 
-        let edit_cost: i32 = if a as u8 == params.query[i as usize] {
-            0
-        } else {
-            1
+    let root = Node {
+        i: (params.query.len() - 1) as i32,
+        edits_left: params.edits as i32,
+        left: 0,
+        right: params.reference.len(),
+        edits: 0,
+        penalty: 0,
+        op: CigarOp::Match,
+        depth: 0,
+    };
+    // Every level of the tree leaves at most one node per child on the stack
+    let max_depth = params.query.len() + params.edits;
+    let mut stack = Vec::with_capacity(max_depth * (2 * ALPHABET.len() - 1));
+    let mut ops = Vec::with_capacity(max_depth);
+    let mut results = HashSet::new();
+    let mut stats = SearchStats::default();
+
+    // A deletion at the end of the query would just shorten the alignment
+    search.expand(&root, false, &mut stack);
+    stats.max_stack = stack.len();
+
+    while let Some(node) = stack.pop() {
+        stats.visited += 1;
+        ops.truncate(node.depth - 1);
+        ops.push(node.op);
+
+        let lower_limit = match usize::try_from(node.i) {
+            Ok(value) => d_table[value],
+            Err(_) => 0,
+        };
+        let within_penalty = match &params.scoring {
+            Some(scoring) => scoring.allows(node.penalty, lower_limit, params.wildcard_cost),
+            None => true,
         };
-        let edits_left = (params.edits as i32) - edit_cost;
-        if edits_left < 0 {
+        if node.edits_left < lower_limit as i32 || !within_penalty {
+            stats.pruned += 1;
             continue;
         }
-        if new_left >= new_right {
+
+        if node.i < 0 {
+            let cigar = ops.iter().rev().copied().collect();
+            results.insert((node.left, node.right, cigar, node.edits));
             continue;
         }
 
-        result = result
-            .union(&inexact_recursion(
-                &inner_params,
-                i - 1,
-                edits_left,
-                new_left,
-                new_right,
-                String::from(if edit_cost == 0 { "M" } else { "S" }),
-                edit_cost as usize,
-            ))
-            .cloned()
-            .collect();
+        search.expand(&node, true, &mut stack);
+        stats.max_stack = stats.max_stack.max(stack.len());
     }
 
-    // I-operation
-    result = result
-        .union(&inexact_recursion(
-            &inner_params,
-            i - 1,
-            (params.edits as i32) - 1,
-            left,
-            right,
-            String::from("I"),
-            1,
-        ))
-        .cloned()
+    (results, stats)
+}
+
+/// Edit cost of aligning the query symbol `query_char` to the reference symbol `c`.
+pub(crate) fn match_cost(c: u8, query_char: u8, wildcard_cost: usize) -> i32 {
+    if query_char == WILDCARD {
+        wildcard_cost as i32
+    } else if c == query_char {
+        0
+    } else {
+        1
+    }
+}
+
+/// Approximative search for the query, and optionally for its reverse complement.
+/// Each result is tagged with the strand it was found on.
+pub fn approx_search_strands<R: Rank + ?Sized>(
+    params: ApproxSearchParams<R>,
+    both_strands: bool,
+) -> StrandAlignments {
+    let mut results: HashSet<_> = approx_search(params)
+        .into_iter()
+        .map(|(left, right, cigar, edits)| (Strand::Forward, left, right, cigar, edits))
         .collect();
 
-    result
+    if both_strands {
+        let reverse = reverse_complement(params.query);
+        let reverse_qualities = reverse_qualities(params.scoring);
+        let reverse_params = ApproxSearchParams {
+            query: &reverse,
+            scoring: params.scoring.map(|scoring| QualityScoring {
+                qualities: &reverse_qualities,
+                ..scoring
+            }),
+            ..params
+        };
+        results.extend(
+            approx_search(reverse_params)
+                .into_iter()
+                .map(|(left, right, cigar, edits)| (Strand::Reverse, left, right, cigar, edits)),
+        );
+    }
+
+    results
 }
 
-fn calculate_d_table(
-    reference: &[u8],
+/// The qualities of the reverse complement of a scored query.
+pub(crate) fn reverse_qualities(scoring: Option<QualityScoring>) -> Vec<u8> {
+    match scoring {
+        Some(scoring) => scoring.qualities.iter().rev().copied().collect(),
+        None => Vec::new(),
+    }
+}
+
+fn calculate_d_table<R: Rank + ?Sized>(
     query: &[u8],
     c_table: &[usize],
-    o_rev_table: &OTable,
+    o_rev_table: &R,
+    wildcard_cost: usize,
 ) -> DTable {
-    let mut start = 1;
-    let mut end = reference.len() - 1;
+    // The half-open interval of the piece reversed, in the index of the reversed
+    // reference
+    let (mut start, mut end) = (0, o_rev_table.len());
     let mut edits_left = 0;
     let mut d_table: DTable = Vec::new();
 
     for c in query {
+        if *c == WILDCARD {
+            // A wildcard never occurs in the reference, so it ends the current piece.
+            // It only contributes to the lower bound if aligning it has a cost.
+            start = 0;
+            end = o_rev_table.len();
+            if wildcard_cost > 0 {
+                edits_left += 1;
+            }
+            d_table.push(edits_left);
+            continue;
+        }
+
         let current_symbol = usize::from(*c);
-        start = c_table[current_symbol] + o_rev_table.get(current_symbol as u8, start - 1) + 1;
-        end = c_table[current_symbol] + o_rev_table.get(current_symbol as u8, end);
-        if start > end {
-            start = 1;
-            end = reference.len() - 1;
+        start = c_table[current_symbol] + o_rev_table.rank(*c, start);
+        end = c_table[current_symbol] + o_rev_table.rank(*c, end);
+        if start >= end {
+            start = 0;
+            end = o_rev_table.len();
             edits_left += 1;
         }
         d_table.push(edits_left);
@@ -114,142 +249,222 @@ fn calculate_d_table(
     d_table
 }
 
-struct InexactRecursionParams<'a> {
-    query: &'a [u8],
-    o_table: &'a OTable<'a>,
-    c_table: &'a [usize],
-    d_table: &'a [usize],
+/// Lower bounds on the mismatches needed to align each prefix of the query
+/// under Hamming distance. The query is cut into pieces like in
+/// `calculate_d_table`, each ending where it stops occurring in the reference.
+/// With substitutions only, a piece that does not occur with one substitution
+/// either needs two, and a wildcard always costs `wildcard_cost`, which bounds
+/// tighter than the one edit per piece that indels would allow.
+fn calculate_hamming_d_table<R: Rank + ?Sized>(
+    query: &[u8],
+    o_table: &R,
+    rev_o_table: &R,
+    c_table: &[usize],
+    wildcard_cost: usize,
+) -> DTable {
+    let mut d_table: DTable = Vec::with_capacity(query.len());
+    let mut mismatches = 0;
+    let mut piece_start = 0;
+    // The interval of the piece reversed, in the index of the reversed reference
+    let (mut start, mut end) = (0, rev_o_table.len());
+
+    for (i, &c) in query.iter().enumerate() {
+        if c == WILDCARD {
+            mismatches += wildcard_cost;
+            piece_start = i + 1;
+            start = 0;
+            end = rev_o_table.len();
+            d_table.push(mismatches);
+            continue;
+        }
+
+        start = c_table[c as usize] + rev_o_table.rank(c, start);
+        end = c_table[c as usize] + rev_o_table.rank(c, end);
+        if start >= end {
+            let piece = &query[piece_start..=i];
+            mismatches += if occurs_with_substitution(piece, o_table, c_table) {
+                1
+            } else {
+                2
+            };
+            piece_start = i + 1;
+            start = 0;
+            end = rev_o_table.len();
+        }
+        d_table.push(mismatches);
+    }
+
+    d_table
 }
 
-fn inexact_recursion(
-    params: &InexactRecursionParams,
-    i: i32,
-    edits_left: i32,
-    left: usize,
-    right: usize,
-    cigar: String,
-    edits_total: usize,
-) -> HashSet<(usize, usize, String, usize)> {
-    let InexactRecursionParams {
-        query,
-        o_table,
-        c_table,
-        d_table,
-    } = params;
-
-    let lower_limit = match usize::try_from(i) {
-        Ok(value) => d_table[value],
-        Err(_) => 0,
+/// Whether the piece occurs in the reference with one of its symbols substituted.
+fn occurs_with_substitution<R: Rank + ?Sized>(
+    piece: &[u8],
+    o_table: &R,
+    c_table: &[usize],
+) -> bool {
+    let extend = |a: u8, (start, end): (usize, usize)| {
+        (
+            c_table[a as usize] + o_table.rank(a, start),
+            c_table[a as usize] + o_table.rank(a, end),
+        )
     };
 
-    if edits_left < lower_limit as i32 {
-        return HashSet::new();
-    }
-
-    let mut result_set = HashSet::new();
+    // The interval of the piece after the substituted position
+    let mut suffix = (0, o_table.len());
+    for (i, &c) in piece.iter().enumerate().rev() {
+        for a in (1..ALPHABET.len() as u8).filter(|&a| a != c) {
+            let mut interval = extend(a, suffix);
+            for &b in piece[..i].iter().rev() {
+                if interval.0 >= interval.1 {
+                    break;
+                }
+                interval = extend(b, interval);
+            }
+            if interval.0 < interval.1 {
+                return true;
+            }
+        }
 
-    if i < 0 {
-        result_set.insert((left, right, cigar, edits_total));
-        return result_set;
+        suffix = extend(c, suffix);
+        if suffix.0 >= suffix.1 {
+            return false;
+        }
     }
+    false
+}
 
-    // Match/substitute operation
-    let current_char = query[i as usize];
-    let mut new_left;
-    let mut new_right;
+struct Backtracking<'a, R: ?Sized> {
+    query: &'a [u8],
+    o_table: &'a R,
+    c_table: &'a [usize],
+    edit_model: EditModel,
+    wildcard_cost: usize,
+    scoring: Option<QualityScoring<'a>>,
+}
 
-    for c in 1..ALPHABET.len() {
-        let c = c as u8;
-        new_left = c_table[c as usize] + o_table.get(c, left);
-        new_right = c_table[c as usize] + o_table.get(c, right);
-        let edit_cost = if c == current_char { 0 } else { 1 };
+/// A node of the backtracking search: the SA interval of the reference aligned
+/// to the query after position `i`, reached by the operation `op`, which is the
+/// `depth`th from the end of the alignment.
+struct Node {
+    i: i32,
+    edits_left: i32,
+    left: usize,
+    right: usize,
+    edits: usize,
+    /// Quality-aware penalty of the operations so far, 0 without scoring
+    penalty: usize,
+    op: CigarOp,
+    depth: usize,
+}
 
-        if (edits_left - edit_cost) < 0 {
-            continue;
-        };
-        if new_left >= new_right {
-            continue;
+impl<R: Rank + ?Sized> Backtracking<'_, R> {
+    /// Pushes the children of a node: matching or substituting the query symbol
+    /// at `i`, inserting it, and deleting a reference symbol if `deletions` is set.
+    /// Their lower bounds are checked when they are taken off the stack.
+    fn expand(&self, node: &Node, deletions: bool, stack: &mut Vec<Node>) {
+        let current_char = self.query[node.i as usize];
+        let gaps = self.edit_model == EditModel::Levenshtein;
+        let depth = node.depth + 1;
+        let penalty = |op: CigarOp| match &self.scoring {
+            Some(scoring) => node.penalty + scoring.penalty(op, current_char, node.i as usize),
+            None => 0,
         };
 
-        result_set = result_set
-            .union(&inexact_recursion(
-                params,
-                i - 1,
-                edits_left - edit_cost,
-                new_left,
-                new_right,
-                format!("{}{}", if edit_cost == 0 { "M" } else { "S" }, cigar),
-                edits_total + edit_cost as usize,
-            ))
-            .cloned()
-            .collect()
-    }
-
-    // Insertion operation
-    result_set = result_set
-        .union(&inexact_recursion(
-            params,
-            i - 1,
-            edits_left - 1,
-            left,
-            right,
-            format!("{}{}", "I", cigar),
-            edits_total + 1,
-        ))
-        .cloned()
-        .collect();
-
-    // Deletion operation
-    for c in 1..ALPHABET.len() {
-        let c = c as u8;
+        for c in 1..ALPHABET.len() as u8 {
+            let left = self.c_table[c as usize] + self.o_table.rank(c, node.left);
+            let right = self.c_table[c as usize] + self.o_table.rank(c, node.right);
+            if left >= right {
+                continue;
+            }
 
-        new_left = c_table[c as usize] + o_table.get(c, left);
-        new_right = c_table[c as usize] + o_table.get(c, right);
+            // Match/substitute operation
+            let edit_cost = match_cost(c, current_char, self.wildcard_cost);
+            if node.edits_left - edit_cost >= 0 {
+                let op = if edit_cost == 0 {
+                    CigarOp::Match
+                } else {
+                    CigarOp::Mismatch
+                };
+                stack.push(Node {
+                    i: node.i - 1,
+                    edits_left: node.edits_left - edit_cost,
+                    left,
+                    right,
+                    edits: node.edits + edit_cost as usize,
+                    penalty: penalty(op),
+                    op,
+                    depth,
+                });
+            }
 
-        if new_left >= new_right {
-            continue;
-        };
+            // Deletion operation
+            if gaps && deletions {
+                stack.push(Node {
+                    i: node.i,
+                    edits_left: node.edits_left - 1,
+                    left,
+                    right,
+                    edits: node.edits + 1,
+                    penalty: penalty(CigarOp::Deletion),
+                    op: CigarOp::Deletion,
+                    depth,
+                });
+            }
+        }
 
-        result_set = result_set
-            .union(&inexact_recursion(
-                params,
-                i,
-                edits_left - 1,
-                new_left,
-                new_right,
-                format!("{}{}", "D", cigar),
-                edits_total + 1,
-            ))
-            .cloned()
-            .collect();
+        // Insertion operation
+        if gaps {
+            stack.push(Node {
+                i: node.i - 1,
+                edits_left: node.edits_left - 1,
+                left: node.left,
+                right: node.right,
+                edits: node.edits + 1,
+                penalty: penalty(CigarOp::Insertion),
+                op: CigarOp::Insertion,
+                depth,
+            });
+        }
     }
-    result_set
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::{
-        bwm, bwt, construct_suffix_array_naive, generate_c_table, remap_reference,
-        sais::suffix_array_induced_sort, util::remap_query,
+        cigar::Cigar,
+        locate::locate_approx,
+        sais::suffix_array_induced_sort,
+        suffix_array_construction::construct_suffix_array_naive,
+        table_gen::generate_c_table,
+        util::{bwm, bwt, remap_query, remap_reference},
+        wavelet_tree::WaveletTree,
     };
 
+    fn cigar(s: &str) -> Cigar {
+        s.parse().unwrap()
+    }
+
     #[test]
     fn test_att_with_1_edit() {
-        let reference = remap_reference::<u8>("AGATAGATTCACA");
+        let reference = remap_reference::<u8>("AGATAGATTCACA").unwrap();
         let suffix_array = construct_suffix_array_naive(&reference);
 
-        let reverse_reference: Vec<u8> = reference.iter().rev().map(|&x| x).collect();
+        let reverse_reference: Vec<u8> = reference.iter().rev().copied().collect();
 
         let reverse_suffix_array = construct_suffix_array_naive(&reverse_reference);
 
         let params = ApproxSearchParams {
             reference: &reference,
-            query: &remap_query("ATT"),
+            query: &remap_query("ATT").unwrap(),
             o_table: &OTable::new(&reference, &suffix_array, 10),
             c_table: &generate_c_table(&reference),
             rev_o_table: &OTable::new(&reverse_reference, &reverse_suffix_array, 10),
             edits: 1,
+            edit_model: EditModel::Levenshtein,
+            wildcard_cost: 1,
+            scoring: None,
         };
 
         let search_result = approx_search(params);
@@ -269,55 +484,61 @@ mod tests {
         }
 
         assert_eq!(search_result.len(), 5);
-        assert!(search_result.contains(&(13, 14, "IMM".to_string(), 1)));
-        assert!(search_result.contains(&(5, 7, "MMI".to_string(), 1)));
-        assert!(search_result.contains(&(5, 7, "MIM".to_string(), 1)));
-        assert!(search_result.contains(&(6, 7, "MMM".to_string(), 0)));
-        assert!(search_result.contains(&(5, 6, "MMS".to_string(), 1)));
+        assert!(search_result.contains(&(13, 14, cigar("1I2="), 1)));
+        assert!(search_result.contains(&(5, 7, cigar("2=1I"), 1)));
+        assert!(search_result.contains(&(5, 7, cigar("1=1I1="), 1)));
+        assert!(search_result.contains(&(6, 7, cigar("3="), 0)));
+        assert!(search_result.contains(&(5, 6, cigar("2=1X"), 1)));
     }
 
     #[test]
     fn test_att_with_0_edit() {
         let genome = "AGATAGATTCACA";
-        let remap_genome = remap_reference(genome);
+        let remap_genome = remap_reference(genome).unwrap();
         let suffix_array = suffix_array_induced_sort(&remap_genome);
 
         let rev_genome: String = genome.chars().rev().collect();
-        let rev_remap_genome = remap_reference(&rev_genome);
+        let rev_remap_genome = remap_reference(&rev_genome).unwrap();
         let rev_suffix_array = suffix_array_induced_sort(&rev_remap_genome);
 
         let params = ApproxSearchParams {
             reference: &remap_genome,
-            query: &remap_query("ATT"),
+            query: &remap_query("ATT").unwrap(),
             o_table: &OTable::new(&remap_genome, &suffix_array, 10),
             c_table: &generate_c_table(&remap_genome),
             rev_o_table: &OTable::new(&rev_remap_genome, &rev_suffix_array, 10),
             edits: 0,
+            edit_model: EditModel::Levenshtein,
+            wildcard_cost: 1,
+            scoring: None,
         };
 
         let search_result = approx_search(params);
         println!("Actual result: {:?}", search_result);
 
         assert_eq!(1, search_result.len());
-        assert!(search_result.contains(&(6, 7, String::from("MMM"), 0)));
+        assert!(search_result.contains(&(6, 7, cigar("3="), 0)));
     }
 
     #[test]
     fn test_simple() {
-        let reference = remap_reference("AAC");
+        let reference = remap_reference("AAC").unwrap();
         let suffix_array = construct_suffix_array_naive(&reference);
 
-        let reverse_reference: Vec<u8> = reference.iter().rev().map(|&x| x).collect();
+        let reverse_reference: Vec<u8> = reference.iter().rev().copied().collect();
 
         let reverse_suffix_array = construct_suffix_array_naive(&reverse_reference);
 
         let params = ApproxSearchParams {
             reference: &reference,
-            query: &remap_query("ACC"),
+            query: &remap_query("ACC").unwrap(),
             o_table: &OTable::new(&reference, &suffix_array, 10),
             c_table: &generate_c_table(&reference),
             rev_o_table: &OTable::new(&reverse_reference, &reverse_suffix_array, 10),
             edits: 1,
+            edit_model: EditModel::Levenshtein,
+            wildcard_cost: 1,
+            scoring: None,
         };
 
         let search_result = approx_search(params);
@@ -330,45 +551,51 @@ mod tests {
 
     #[test]
     fn test_substitute() {
-        let reference = remap_reference("ACG");
+        let reference = remap_reference("ACG").unwrap();
         let suffix_array = construct_suffix_array_naive(&reference);
 
-        let reverse_reference: Vec<u8> = reference.iter().rev().map(|&x| x).collect();
+        let reverse_reference: Vec<u8> = reference.iter().rev().copied().collect();
 
         let reverse_suffix_array = construct_suffix_array_naive(&reverse_reference);
 
         let params = ApproxSearchParams {
             reference: &reference,
-            query: &remap_query("AGG"),
+            query: &remap_query("AGG").unwrap(),
             o_table: &OTable::new(&reference, &suffix_array, 10),
             c_table: &generate_c_table(&reference),
             rev_o_table: &OTable::new(&reverse_reference, &reverse_suffix_array, 10),
             edits: 1,
+            edit_model: EditModel::Levenshtein,
+            wildcard_cost: 1,
+            scoring: None,
         };
 
         let results = approx_search(params);
         println!("Actual result: {:?}", results);
 
         assert_eq!(results.len(), 1);
-        assert!(results.contains(&(1, 2, "MSM".to_string(), 1)));
+        assert!(results.contains(&(1, 2, cigar("1=1X1="), 1)));
     }
 
     #[test]
     fn test_delete() {
-        let reference = remap_reference("TACGT");
+        let reference = remap_reference("TACGT").unwrap();
         let suffix_array = construct_suffix_array_naive(&reference);
 
-        let reverse_reference: Vec<u8> = reference.iter().rev().map(|&x| x).collect();
+        let reverse_reference: Vec<u8> = reference.iter().rev().copied().collect();
 
         let reverse_suffix_array = construct_suffix_array_naive(&reverse_reference);
 
         let params = ApproxSearchParams {
             reference: &reference,
-            query: &remap_query("TAGT"),
+            query: &remap_query("TAGT").unwrap(),
             o_table: &OTable::new(&reference, &suffix_array, 10),
             c_table: &generate_c_table(&reference),
             rev_o_table: &OTable::new(&reverse_reference, &reverse_suffix_array, 10),
             edits: 1,
+            edit_model: EditModel::Levenshtein,
+            wildcard_cost: 1,
+            scoring: None,
         };
 
         let results = approx_search(params);
@@ -379,7 +606,7 @@ mod tests {
             Some((start, end, edits, num_edits)) => {
                 assert_eq!(start, &5);
                 assert_eq!(end, &6);
-                assert_eq!(edits, "MMDMM");
+                assert_eq!(edits.to_string(), "2=1D2=");
                 assert_eq!(num_edits, &1);
             }
             None => {
@@ -390,20 +617,23 @@ mod tests {
 
     #[test]
     fn test_insert() {
-        let reference = remap_reference("AC");
+        let reference = remap_reference("AC").unwrap();
         let suffix_array = construct_suffix_array_naive(&reference);
 
-        let reverse_reference: Vec<u8> = reference.iter().rev().map(|&x| x).collect();
+        let reverse_reference: Vec<u8> = reference.iter().rev().copied().collect();
 
         let reverse_suffix_array = construct_suffix_array_naive(&reverse_reference);
 
         let params = ApproxSearchParams {
             reference: &reference,
-            query: &remap_query("ACG"),
+            query: &remap_query("ACG").unwrap(),
             o_table: &OTable::new(&reference, &suffix_array, 10),
             c_table: &generate_c_table(&reference),
             rev_o_table: &OTable::new(&reverse_reference, &reverse_suffix_array, 10),
             edits: 1,
+            edit_model: EditModel::Levenshtein,
+            wildcard_cost: 1,
+            scoring: None,
         };
 
         let results = approx_search(params);
@@ -414,7 +644,7 @@ mod tests {
             Some((start, end, edits, num_edits)) => {
                 assert_eq!(start, &1);
                 assert_eq!(end, &2);
-                assert_eq!(edits, "MMI");
+                assert_eq!(edits.to_string(), "2=1I");
                 assert_eq!(num_edits, &1);
             }
             None => {
@@ -423,22 +653,318 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_hamming_skips_insertions_and_deletions() {
+        let reference = remap_reference("AGATAGATTCACAGGATTACANNGATTTACAGATTACA").unwrap();
+        let suffix_array = construct_suffix_array_naive(&reference);
+        let mut reverse_reference = reference[..reference.len() - 1].to_vec();
+        reverse_reference.reverse();
+        reverse_reference.push(0);
+        let reverse_suffix_array = construct_suffix_array_naive(&reverse_reference);
+        let o_table = OTable::new(&reference, &suffix_array, 10);
+        let rev_o_table = OTable::new(&reverse_reference, &reverse_suffix_array, 10);
+        let c_table = generate_c_table(&reference);
+
+        for read in ["GATTACA", "GATTTCA", "CAGNATT", "TTTTTTT"] {
+            let query = remap_query(read).unwrap();
+            for edits in 0..=2 {
+                let params = ApproxSearchParams {
+                    reference: &reference,
+                    query: &query,
+                    o_table: &o_table,
+                    c_table: &c_table,
+                    rev_o_table: &rev_o_table,
+                    edits,
+                    edit_model: EditModel::Levenshtein,
+                    wildcard_cost: 1,
+                    scoring: None,
+                };
+                let substitutions: HashSet<_> = approx_search(params)
+                    .into_iter()
+                    .filter(|(_, _, cigar, _)| !cigar.has_indels())
+                    .collect();
+                let hamming = approx_search(ApproxSearchParams {
+                    edit_model: EditModel::Hamming,
+                    ..params
+                });
+                assert_eq!(hamming, substitutions, "{} with {} edits", read, edits);
+            }
+        }
+    }
+
+    #[test]
+    fn test_d_table() {
+        let reference = remap_reference("ATAAATTCCAGGC").unwrap();
+        let suffix_array = construct_suffix_array_naive(&reference);
+        let mut reverse_reference = reference[..reference.len() - 1].to_vec();
+        reverse_reference.reverse();
+        reverse_reference.push(0);
+        let reverse_suffix_array = construct_suffix_array_naive(&reverse_reference);
+        let o_table = OTable::new(&reference, &suffix_array, 10);
+        let rev_o_table = OTable::new(&reverse_reference, &reverse_suffix_array, 10);
+        let c_table = generate_c_table(&reference);
+        let query = remap_query("ATTTCCAG").unwrap();
+
+        // ATTT does not occur, but TCCAG after it does
+        let d_table = calculate_d_table(&query, &c_table, &rev_o_table, 1);
+        assert_eq!(d_table, vec![0, 0, 0, 1, 1, 1, 1, 1]);
+
+        let search_result = approx_search(ApproxSearchParams {
+            reference: &reference,
+            query: &query,
+            o_table: &o_table,
+            c_table: &c_table,
+            rev_o_table: &rev_o_table,
+            edits: 1,
+            edit_model: EditModel::Levenshtein,
+            wildcard_cost: 1,
+            scoring: None,
+        });
+        assert_eq!(search_result.len(), 4);
+        assert!(search_result.contains(&(5, 6, cigar("2=1I5="), 1)));
+        assert!(search_result.contains(&(2, 3, cigar("1=1X6="), 1)));
+    }
+
+    #[test]
+    fn test_hamming_d_table() {
+        let reference = remap_reference("ACGTACGT").unwrap();
+        let suffix_array = construct_suffix_array_naive(&reference);
+        let mut reverse_reference = reference[..reference.len() - 1].to_vec();
+        reverse_reference.reverse();
+        reverse_reference.push(0);
+        let reverse_suffix_array = construct_suffix_array_naive(&reverse_reference);
+        let o_table = OTable::new(&reference, &suffix_array, 10);
+        let rev_o_table = OTable::new(&reverse_reference, &reverse_suffix_array, 10);
+        let c_table = generate_c_table(&reference);
+        let d_table = |query: &str, wildcard_cost| {
+            let query = remap_query(query).unwrap();
+            calculate_hamming_d_table(&query, &o_table, &rev_o_table, &c_table, wildcard_cost)
+        };
+
+        // AA is one substitution from AC, while TACGTA is one insertion from
+        // ACGTA but two substitutions from anything in the reference
+        assert_eq!(d_table("AACGT", 1), vec![0, 1, 1, 1, 1]);
+        assert_eq!(d_table("TACGTA", 1), vec![0, 0, 0, 0, 0, 2]);
+        assert_eq!(d_table("GNA", 0), vec![0, 0, 0]);
+        assert_eq!(d_table("GNA", 2), vec![0, 2, 2]);
+    }
+
+    #[test]
+    fn test_wildcard_cost() {
+        let reference = remap_reference("AGATAGATTCACA").unwrap();
+        let suffix_array = construct_suffix_array_naive(&reference);
+        let reverse_reference: Vec<u8> = reference.iter().rev().copied().collect();
+        let reverse_suffix_array = construct_suffix_array_naive(&reverse_reference);
+        let o_table = OTable::new(&reference, &suffix_array, 10);
+        let c_table = generate_c_table(&reference);
+        let rev_o_table = OTable::new(&reverse_reference, &reverse_suffix_array, 10);
+        let query = remap_query("ANA").unwrap();
+
+        let params = ApproxSearchParams {
+            reference: &reference,
+            query: &query,
+            o_table: &o_table,
+            c_table: &c_table,
+            rev_o_table: &rev_o_table,
+            edits: 0,
+            edit_model: EditModel::Levenshtein,
+            wildcard_cost: 0,
+            scoring: None,
+        };
+        let free = approx_search(params);
+        println!("Actual result: {:?}", free);
+        assert_eq!(locate_approx(&suffix_array, &free, None), vec![0, 2, 4, 10]);
+        assert!(free
+            .iter()
+            .all(|(_, _, cigar, edits)| cigar.to_string() == "3=" && *edits == 0));
+
+        let costly = approx_search(ApproxSearchParams {
+            wildcard_cost: 1,
+            ..params
+        });
+        assert!(costly.is_empty());
+
+        let costly = approx_search(ApproxSearchParams {
+            wildcard_cost: 1,
+            edits: 1,
+            ..params
+        });
+        let substitutions: HashSet<_> = costly
+            .into_iter()
+            .filter(|(_, _, cigar, _)| cigar.to_string() == "1=1X1=")
+            .collect();
+        assert_eq!(
+            locate_approx(&suffix_array, &substitutions, None),
+            vec![0, 2, 4, 10]
+        );
+    }
+
+    #[test]
+    fn test_wavelet_tree_matches_o_table() {
+        let reference = remap_reference("AGATAGATTCACAGGATTACA").unwrap();
+        let suffix_array = construct_suffix_array_naive(&reference);
+        let reverse_reference: Vec<u8> = reference.iter().rev().copied().collect();
+        let reverse_suffix_array = construct_suffix_array_naive(&reverse_reference);
+        let c_table = generate_c_table(&reference);
+        let query = remap_query("GATTA").unwrap();
+
+        let o_table = OTable::new(&reference, &suffix_array, 10);
+        let rev_o_table = OTable::new(&reverse_reference, &reverse_suffix_array, 10);
+        let tree = WaveletTree::from_suffix_array(&reference, &suffix_array);
+        let rev_tree = WaveletTree::from_suffix_array(&reverse_reference, &reverse_suffix_array);
+
+        let params = ApproxSearchParams {
+            reference: &reference,
+            query: &query,
+            o_table: &o_table,
+            c_table: &c_table,
+            rev_o_table: &rev_o_table,
+            edits: 2,
+            edit_model: EditModel::Levenshtein,
+            wildcard_cost: 1,
+            scoring: None,
+        };
+        let expected = approx_search(params);
+        assert!(!expected.is_empty());
+        assert_eq!(
+            approx_search(ApproxSearchParams {
+                reference: &reference,
+                query: &query,
+                o_table: &tree,
+                c_table: &c_table,
+                rev_o_table: &rev_tree,
+                edits: 2,
+                edit_model: EditModel::Levenshtein,
+                wildcard_cost: 1,
+                scoring: None,
+            }),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_reverse_strand() {
+        let reference = remap_reference("TACGT").unwrap();
+        let suffix_array = construct_suffix_array_naive(&reference);
+        let reverse_reference: Vec<u8> = reference.iter().rev().copied().collect();
+        let reverse_suffix_array = construct_suffix_array_naive(&reverse_reference);
+
+        // Reverse complement of TAGT from test_delete
+        let params = ApproxSearchParams {
+            reference: &reference,
+            query: &remap_query("ACTA").unwrap(),
+            o_table: &OTable::new(&reference, &suffix_array, 10),
+            c_table: &generate_c_table(&reference),
+            rev_o_table: &OTable::new(&reverse_reference, &reverse_suffix_array, 10),
+            edits: 1,
+            edit_model: EditModel::Levenshtein,
+            wildcard_cost: 1,
+            scoring: None,
+        };
+
+        let forward_only = approx_search_strands(params, false);
+        assert!(forward_only
+            .iter()
+            .all(|(strand, ..)| *strand == Strand::Forward));
+
+        let both = approx_search_strands(params, true);
+        println!("Actual result: {:?}", both);
+        assert!(both.contains(&(Strand::Reverse, 5, 6, cigar("2=1D2="), 1)));
+    }
+
+    #[test]
+    fn test_search_stats() {
+        let reference = remap_reference("AGATAGATTCACA").unwrap();
+        let suffix_array = construct_suffix_array_naive(&reference);
+        let reverse_reference: Vec<u8> = reference.iter().rev().copied().collect();
+        let reverse_suffix_array = construct_suffix_array_naive(&reverse_reference);
+
+        let params = ApproxSearchParams {
+            reference: &reference,
+            query: &remap_query("GAT").unwrap(),
+            o_table: &OTable::new(&reference, &suffix_array, 10),
+            c_table: &generate_c_table(&reference),
+            rev_o_table: &OTable::new(&reverse_reference, &reverse_suffix_array, 10),
+            edits: 0,
+            edit_model: EditModel::Hamming,
+            wildcard_cost: 1,
+            scoring: None,
+        };
+
+        // Without edits, only the matching path is ever on the stack
+        let (results, stats) = approx_search_with_stats(params);
+        assert_eq!(results.len(), 1);
+        assert!(results
+            .iter()
+            .all(|(left, right, cigar, _)| right - left == 2 && cigar.to_string() == "3="));
+        let expected = SearchStats {
+            visited: 3,
+            pruned: 0,
+            max_stack: 1,
+        };
+        assert_eq!(stats, expected);
+
+        // Insertions and deletions are pushed too, and cut off when visited
+        let params = ApproxSearchParams {
+            edit_model: EditModel::Levenshtein,
+            ..params
+        };
+        let (levenshtein_results, stats) = approx_search_with_stats(params);
+        assert_eq!(levenshtein_results, results);
+        assert_eq!(stats.visited, 3 + stats.pruned);
+        assert!(stats.pruned > 0);
+
+        let params = ApproxSearchParams { edits: 2, ..params };
+        let (results, stats) = approx_search_with_stats(params);
+        assert_eq!(results, approx_search(params));
+        assert!(stats.visited > results.len());
+        assert!(stats.max_stack <= (params.query.len() + 2) * 9);
+    }
+
+    #[test]
+    fn test_empty_query() {
+        let reference = remap_reference("ACGT").unwrap();
+        let suffix_array = construct_suffix_array_naive(&reference);
+        let reverse_reference: Vec<u8> = reference.iter().rev().copied().collect();
+        let reverse_suffix_array = construct_suffix_array_naive(&reverse_reference);
+
+        for edit_model in [EditModel::Levenshtein, EditModel::Hamming] {
+            let params = ApproxSearchParams {
+                reference: &reference,
+                query: &[],
+                o_table: &OTable::new(&reference, &suffix_array, 10),
+                c_table: &generate_c_table(&reference),
+                rev_o_table: &OTable::new(&reverse_reference, &reverse_suffix_array, 10),
+                edits: 1,
+                edit_model,
+                wildcard_cost: 1,
+                scoring: None,
+            };
+
+            assert!(approx_search(params).is_empty());
+            assert!(approx_search_strands(params, true).is_empty());
+        }
+    }
+
     #[test]
     fn test_too_long_query() {
-        let reference = remap_reference("ACGT");
+        let reference = remap_reference("ACGT").unwrap();
         let suffix_array = construct_suffix_array_naive(&reference);
 
-        let reverse_reference: Vec<u8> = reference.iter().rev().map(|&x| x).collect();
+        let reverse_reference: Vec<u8> = reference.iter().rev().copied().collect();
 
         let reverse_suffix_array = construct_suffix_array_naive(&reverse_reference);
 
         let params = ApproxSearchParams {
             reference: &reference,
-            query: &remap_query("ACGTGTGT"),
+            query: &remap_query("ACGTGTGT").unwrap(),
             o_table: &OTable::new(&reference, &suffix_array, 10),
             c_table: &generate_c_table(&reference),
             rev_o_table: &OTable::new(&reverse_reference, &reverse_suffix_array, 10),
             edits: 1,
+            edit_model: EditModel::Levenshtein,
+            wildcard_cost: 1,
+            scoring: None,
         };
 
         let results = approx_search(params);
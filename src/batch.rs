@@ -0,0 +1,68 @@
+use std::panic;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// Number of items a worker claims at a time. Small enough that threads finish
+/// together when some reads take much longer than others.
+const CHUNK_SIZE: usize = 64;
+
+/// Applies `f` to every item on `threads` worker threads, returning the results
+/// in the order of the items. Workers share the captured state of `f`, such as an
+/// index, instead of each getting a copy.
+pub fn map_in_order<T, R, F>(items: &[T], threads: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let threads = threads.max(1).min(items.len().div_ceil(CHUNK_SIZE));
+    if threads <= 1 {
+        return items.iter().map(f).collect();
+    }
+
+    let next_chunk = AtomicUsize::new(0);
+    let mut chunks: Vec<(usize, Vec<R>)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let start = next_chunk.fetch_add(CHUNK_SIZE, Ordering::Relaxed);
+                        if start >= items.len() {
+                            return done;
+                        }
+                        let end = (start + CHUNK_SIZE).min(items.len());
+                        done.push((start, items[start..end].iter().map(&f).collect()));
+                    }
+                })
+            })
+            .collect();
+
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+            .collect()
+    });
+
+    chunks.sort_unstable_by_key(|&(start, _)| start);
+    chunks
+        .into_iter()
+        .flat_map(|(_, results)| results)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_in_order() {
+        let items: Vec<usize> = (0..1000).collect();
+        let expected: Vec<usize> = items.iter().map(|i| i * i).collect();
+
+        for threads in [1, 3, 8] {
+            assert_eq!(map_in_order(&items, threads, |i| i * i), expected);
+        }
+        assert!(map_in_order(&[] as &[usize], 4, |i| *i).is_empty());
+    }
+}
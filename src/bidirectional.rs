@@ -0,0 +1,242 @@
+use crate::o_table::OTable;
+use crate::rank::Rank;
+use crate::util::WILDCARD;
+
+/// The SA interval of a pattern in the forward index, paired with the interval
+/// of the reversed pattern in the index of the reversed genome. Both hold one row
+/// per occurrence, so they share a length.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BiInterval {
+    /// First row in the forward suffix array
+    pub forward: usize,
+    /// First row in the suffix array of the reversed genome
+    pub reverse: usize,
+    pub len: usize,
+}
+
+impl BiInterval {
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The half-open forward interval, as returned by approximate search.
+    pub fn forward_range(&self) -> (usize, usize) {
+        (self.forward, self.forward + self.len)
+    }
+
+    /// The half-open interval in the suffix array of the reversed genome.
+    pub fn reverse_range(&self) -> (usize, usize) {
+        (self.reverse, self.reverse + self.len)
+    }
+}
+
+/// A bidirectional FM-index over the rank structures of a genome and of its
+/// reverse, which extends a match by one symbol at either end while keeping the
+/// forward and reverse intervals in sync.
+///
+/// Extending to the left narrows the forward interval by LF-mapping as in
+/// backward search. The occurrences preceded by the new symbol form a block of
+/// the reverse interval, starting after those preceded by a smaller symbol, so
+/// counting those in the forward interval gives where the block starts.
+/// Extending to the right does the same with the roles of the indexes swapped.
+pub struct BidirectionalIndex<'a, R: ?Sized = OTable> {
+    o_table: &'a R,
+    reverse_o_table: &'a R,
+    /// Shared by both directions, as a genome and its reverse have the same symbols
+    c_table: &'a [usize],
+}
+
+impl<'a, R: Rank + ?Sized> BidirectionalIndex<'a, R> {
+    /// Pairs the rank structures of a genome and of its reverse, which must
+    /// both end with the sentinel, as the genomes of `FmIndex` do.
+    pub fn new(o_table: &'a R, reverse_o_table: &'a R, c_table: &'a [usize]) -> Self {
+        BidirectionalIndex {
+            o_table,
+            reverse_o_table,
+            c_table,
+        }
+    }
+
+    /// The interval of the empty pattern, covering every row.
+    pub fn full(&self) -> BiInterval {
+        BiInterval {
+            forward: 0,
+            reverse: 0,
+            len: self.o_table.len(),
+        }
+    }
+
+    /// The interval of `aP`, given the interval of `P`. Wildcards never match.
+    pub fn extend_left(&self, interval: BiInterval, a: u8) -> BiInterval {
+        let (forward, reverse) = extend(
+            self.o_table,
+            self.c_table,
+            interval.forward,
+            interval.reverse,
+            interval.len,
+            a,
+        );
+        BiInterval {
+            forward: forward.0,
+            reverse,
+            len: forward.1,
+        }
+    }
+
+    /// The interval of `Pa`, given the interval of `P`. Wildcards never match.
+    pub fn extend_right(&self, interval: BiInterval, a: u8) -> BiInterval {
+        let (reverse, forward) = extend(
+            self.reverse_o_table,
+            self.c_table,
+            interval.reverse,
+            interval.forward,
+            interval.len,
+            a,
+        );
+        BiInterval {
+            forward,
+            reverse: reverse.0,
+            len: reverse.1,
+        }
+    }
+
+    /// The interval of a remapped pattern, found by extending to the left.
+    pub fn search(&self, query: &[u8]) -> BiInterval {
+        let mut interval = self.full();
+        for &a in query.iter().rev() {
+            if interval.is_empty() {
+                break;
+            }
+            interval = self.extend_left(interval, a);
+        }
+        interval
+    }
+}
+
+/// Extends an interval by LF-mapping through `o_table`, returning its new start
+/// and length there, and the new start of the interval it is paired with.
+fn extend<R: Rank + ?Sized>(
+    o_table: &R,
+    c_table: &[usize],
+    start: usize,
+    other_start: usize,
+    len: usize,
+    a: u8,
+) -> ((usize, usize), usize) {
+    if a == WILDCARD || len == 0 {
+        return ((0, 0), 0);
+    }
+
+    let end = start + len;
+    let mut smaller = 0;
+    for b in 0..a {
+        smaller += o_table.rank(b, end) - o_table.rank(b, start);
+    }
+    let from = o_table.rank(a, start);
+    let to = o_table.rank(a, end);
+    (
+        (c_table[a as usize] + from, to - from),
+        other_start + smaller,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exact_search::bwt_search;
+    use crate::sais::suffix_array_induced_sort;
+    use crate::table_gen::generate_c_table;
+    use crate::util::{remap_query, remap_reference};
+    use crate::wavelet_tree::WaveletTree;
+
+    struct Tables {
+        o_table: OTable,
+        reverse_o_table: OTable,
+        c_table: Vec<usize>,
+    }
+
+    fn tables(genome: &str) -> Tables {
+        let genome = remap_reference(genome).unwrap();
+        let mut reverse_genome = genome[..genome.len() - 1].to_vec();
+        reverse_genome.reverse();
+        reverse_genome.push(0);
+        let sa = suffix_array_induced_sort(&genome);
+        let reverse_sa = suffix_array_induced_sort(&reverse_genome);
+        Tables {
+            o_table: OTable::new(&genome, &sa, 4),
+            reverse_o_table: OTable::new(&reverse_genome, &reverse_sa, 4),
+            c_table: generate_c_table(&genome),
+        }
+    }
+
+    /// The expected interval of a pattern, from backward search in both indexes.
+    fn expected(tables: &Tables, pattern: &str) -> BiInterval {
+        let query = remap_query(pattern).unwrap();
+        let reverse_query: Vec<u8> = query.iter().rev().copied().collect();
+        let (forward, end) = bwt_search(&query, &tables.o_table, &tables.c_table);
+        let (reverse, _) = bwt_search(&reverse_query, &tables.reverse_o_table, &tables.c_table);
+        BiInterval {
+            forward,
+            reverse,
+            len: (end + 1).saturating_sub(forward),
+        }
+    }
+
+    #[test]
+    fn test_extend_in_any_order() {
+        let tables = tables("ACGTNNATCGTGACGGGCTATAGCGANNNTGTCGATGCAATTGGCCATGCAGTNACGT");
+        let index =
+            BidirectionalIndex::new(&tables.o_table, &tables.reverse_o_table, &tables.c_table);
+
+        for pattern in ["CG", "GAT", "ATGCA", "ACGT", "TTTT"] {
+            let query = remap_query(pattern).unwrap();
+            let expected = expected(&tables, pattern);
+            assert_eq!(index.search(&query).len, expected.len);
+
+            // Start from every position of the pattern and grow it both ways
+            for seed in 0..query.len() {
+                let mut interval = index.extend_left(index.full(), query[seed]);
+                for &a in &query[seed + 1..] {
+                    interval = index.extend_right(interval, a);
+                }
+                for &a in query[..seed].iter().rev() {
+                    interval = index.extend_left(interval, a);
+                }
+
+                if expected.is_empty() {
+                    assert!(interval.is_empty(), "{} from {}", pattern, seed);
+                } else {
+                    assert_eq!(interval, expected, "{} from {}", pattern, seed);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_wildcards_never_match() {
+        let tables = tables("ACGTACGT");
+        let index =
+            BidirectionalIndex::new(&tables.o_table, &tables.reverse_o_table, &tables.c_table);
+        assert!(index.search(&remap_query("ANG").unwrap()).is_empty());
+        assert!(index.extend_right(index.full(), WILDCARD).is_empty());
+    }
+
+    #[test]
+    fn test_over_wavelet_trees() {
+        let genome = "ATTGCAGGATTACAGATTACA";
+        let tables = tables(genome);
+        let trees: Vec<WaveletTree> = [&tables.o_table, &tables.reverse_o_table]
+            .iter()
+            .map(|o_table| {
+                let bwt: Vec<u8> = (0..o_table.len()).map(|i| o_table.symbol(i)).collect();
+                WaveletTree::new(&bwt)
+            })
+            .collect();
+        let index = BidirectionalIndex::new(&trees[0], &trees[1], &tables.c_table);
+
+        let query = remap_query("TTAC").unwrap();
+        let interval = index.extend_right(index.extend_left(index.full(), query[1]), query[2]);
+        let interval = index.extend_right(index.extend_left(interval, query[0]), query[3]);
+        assert_eq!(interval, expected(&tables, "TTAC"));
+    }
+}
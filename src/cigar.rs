@@ -0,0 +1,265 @@
+use crate::error::GeneSearchError;
+use std::fmt::{Display, Formatter};
+use std::iter::FromIterator;
+use std::str::FromStr;
+
+/// An operation of an alignment of a read to the reference.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum CigarOp {
+    /// A read base aligned at no cost, to an equal base or as a free wildcard
+    Match,
+    /// A read base aligned to another base, or a wildcard aligned at a cost
+    Mismatch,
+    /// A read base missing from the reference
+    Insertion,
+    /// A reference base missing from the read
+    Deletion,
+    /// A read base left out of the alignment
+    SoftClip,
+}
+
+impl CigarOp {
+    /// The SAM character of the operation, telling matches from mismatches.
+    pub fn sam_char(self) -> char {
+        match self {
+            CigarOp::Match => '=',
+            CigarOp::Mismatch => 'X',
+            CigarOp::Insertion => 'I',
+            CigarOp::Deletion => 'D',
+            CigarOp::SoftClip => 'S',
+        }
+    }
+
+    /// The SAM character of the operation, with matches and mismatches both `M`.
+    pub fn sam_char_merged(self) -> char {
+        match self {
+            CigarOp::Match | CigarOp::Mismatch => 'M',
+            op => op.sam_char(),
+        }
+    }
+
+    pub fn consumes_query(self) -> bool {
+        self != CigarOp::Deletion
+    }
+
+    pub fn consumes_reference(self) -> bool {
+        matches!(self, CigarOp::Match | CigarOp::Mismatch | CigarOp::Deletion)
+    }
+
+    fn from_sam_char(c: char) -> Option<Self> {
+        match c {
+            '=' => Some(CigarOp::Match),
+            'X' => Some(CigarOp::Mismatch),
+            'I' => Some(CigarOp::Insertion),
+            'D' => Some(CigarOp::Deletion),
+            'S' => Some(CigarOp::SoftClip),
+            _ => None,
+        }
+    }
+}
+
+/// The operations of an alignment from its leftmost base, run-length encoded.
+/// Adjacent runs always hold different operations, so two CIGARs are equal
+/// exactly when their operations are.
+///
+/// Displays in SAM format with `=` and `X`, and parses from it. SAM's `M` is
+/// rejected when parsing, as it does not tell matches from mismatches.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Cigar {
+    runs: Vec<(usize, CigarOp)>,
+}
+
+impl Cigar {
+    pub fn new() -> Self {
+        Cigar::default()
+    }
+
+    /// Appends an operation at the right end.
+    pub fn push(&mut self, op: CigarOp) {
+        self.push_run(1, op);
+    }
+
+    /// Appends `len` copies of an operation at the right end.
+    pub fn push_run(&mut self, len: usize, op: CigarOp) {
+        if len == 0 {
+            return;
+        }
+        match self.runs.last_mut() {
+            Some((run, last)) if *last == op => *run += len,
+            _ => self.runs.push((len, op)),
+        }
+    }
+
+    /// The runs of the CIGAR as `(length, operation)`.
+    pub fn runs(&self) -> &[(usize, CigarOp)] {
+        &self.runs
+    }
+
+    /// Every operation, one per base.
+    pub fn ops(&self) -> impl Iterator<Item = CigarOp> + '_ {
+        self.runs
+            .iter()
+            .flat_map(|&(len, op)| std::iter::repeat_n(op, len))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+
+    /// Whether the alignment inserts or deletes any bases.
+    pub fn has_indels(&self) -> bool {
+        self.runs
+            .iter()
+            .any(|(_, op)| matches!(op, CigarOp::Insertion | CigarOp::Deletion))
+    }
+
+    /// Number of read bases the alignment covers, soft clipped ones included.
+    pub fn query_len(&self) -> usize {
+        self.runs
+            .iter()
+            .filter(|(_, op)| op.consumes_query())
+            .map(|(len, _)| len)
+            .sum()
+    }
+
+    /// Number of reference bases the alignment covers.
+    pub fn reference_span(&self) -> usize {
+        self.runs
+            .iter()
+            .filter(|(_, op)| op.consumes_reference())
+            .map(|(len, _)| len)
+            .sum()
+    }
+
+    /// The CIGAR in SAM format with matches and mismatches both written as `M`,
+    /// as most tools expect. An empty CIGAR is `*`.
+    pub fn to_sam(&self) -> String {
+        let mut merged = Cigar::new();
+        for &(len, op) in &self.runs {
+            let op = match op {
+                CigarOp::Mismatch => CigarOp::Match,
+                op => op,
+            };
+            merged.push_run(len, op);
+        }
+        if merged.is_empty() {
+            return "*".to_string();
+        }
+        merged
+            .runs
+            .iter()
+            .map(|(len, op)| format!("{}{}", len, op.sam_char_merged()))
+            .collect()
+    }
+}
+
+impl FromIterator<CigarOp> for Cigar {
+    fn from_iter<I: IntoIterator<Item = CigarOp>>(iter: I) -> Self {
+        let mut cigar = Cigar::new();
+        for op in iter {
+            cigar.push(op);
+        }
+        cigar
+    }
+}
+
+impl Display for Cigar {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return write!(f, "*");
+        }
+        for (len, op) in &self.runs {
+            write!(f, "{}{}", len, op.sam_char())?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Cigar {
+    type Err = GeneSearchError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| GeneSearchError::Parse(format!("CIGAR {}: {}", s, reason));
+        let mut cigar = Cigar::new();
+        if s == "*" {
+            return Ok(cigar);
+        }
+
+        let mut len: Option<usize> = None;
+        for c in s.chars() {
+            if let Some(digit) = c.to_digit(10) {
+                len = Some(len.unwrap_or(0) * 10 + digit as usize);
+                continue;
+            }
+            let op = match CigarOp::from_sam_char(c) {
+                Some(op) => op,
+                None if c == 'M' => return Err(invalid("M is ambiguous, use = and X")),
+                None => return Err(invalid(&format!("unknown operation {}", c))),
+            };
+            match len.take() {
+                Some(len) if len > 0 => cigar.push_run(len, op),
+                _ => return Err(invalid(&format!("{} needs a positive length", c))),
+            }
+        }
+        if len.is_some() {
+            return Err(invalid("ends with a length"));
+        }
+        Ok(cigar)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runs_merge() {
+        let cigar: Cigar = [
+            CigarOp::Match,
+            CigarOp::Match,
+            CigarOp::Deletion,
+            CigarOp::Match,
+            CigarOp::Mismatch,
+        ]
+        .iter()
+        .copied()
+        .collect();
+
+        assert_eq!(
+            cigar.runs(),
+            &[
+                (2, CigarOp::Match),
+                (1, CigarOp::Deletion),
+                (1, CigarOp::Match),
+                (1, CigarOp::Mismatch)
+            ]
+        );
+        assert_eq!(cigar.to_string(), "2=1D1=1X");
+        assert_eq!(cigar.to_sam(), "2M1D2M");
+        assert_eq!(cigar.ops().count(), 5);
+        assert!(cigar.has_indels());
+    }
+
+    #[test]
+    fn test_lengths() {
+        let cigar: Cigar = "2S3=1I1X2D4=".parse().unwrap();
+        assert_eq!(cigar.query_len(), 11);
+        assert_eq!(cigar.reference_span(), 10);
+        assert_eq!(cigar.to_sam(), "2S3M1I1M2D4M");
+    }
+
+    #[test]
+    fn test_parse_round_trip() {
+        for s in ["1I2=", "10=1X12=", "1=1D1=", "*"] {
+            assert_eq!(s.parse::<Cigar>().unwrap().to_string(), s);
+        }
+        assert_eq!(Cigar::new().to_sam(), "*");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for s in ["3M", "=", "0I", "2=3", "2Q"] {
+            assert!(s.parse::<Cigar>().is_err(), "{}", s);
+        }
+    }
+}
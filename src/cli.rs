@@ -0,0 +1,330 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[derive(Parser)]
+#[command(
+    name = "gene_search",
+    version,
+    about = "Matches reads to genomes using suffix arrays and FM-index search"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Build the suffix arrays and O-tables of a genome and cache them on disk
+    Index(GenomeArgs),
+    /// Map reads to a genome and write the alignments as SAM
+    Map(SearchArgs),
+    /// Count the number of positions each read occurs at
+    Count(SearchArgs),
+    /// Print the 0-based positions each read occurs at
+    Locate(SearchArgs),
+    /// Time one of the algorithms, printing the average runtime in nanoseconds
+    Bench(BenchArgs),
+    /// Print statistics about a genome and the size of its index
+    Stats(GenomeArgs),
+}
+
+#[derive(Args)]
+pub struct GenomeArgs {
+    /// Genome file in FASTA format, optionally gzip-compressed
+    #[arg(short, long, required_unless_present = "index")]
+    pub genome: Option<PathBuf>,
+
+    /// Index file to use. Without a genome it is memory-mapped as is; with one it
+    /// is checked against the genome, and rebuilt if it does not match. Defaults
+    /// to a cached index in resources/index
+    #[arg(short = 'x', long)]
+    pub index: Option<PathBuf>,
+
+    /// Distance between the sampled columns of the O-table, when building an index
+    #[arg(short, long, default_value_t = 10, value_parser = positive::<usize>)]
+    pub spacing: usize,
+
+    /// Memory layout of the O-tables, when building an index
+    #[arg(long, value_enum, default_value_t = Layout::Separate)]
+    pub layout: Layout,
+
+    /// Replace N and other ambiguity codes in the genome with random bases,
+    /// instead of keeping them as symbols that never match
+    #[arg(long)]
+    pub randomize_n: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Layout {
+    /// A row of sampled counts per symbol, next to the packed BWT
+    Separate,
+    /// Sampled counts stored with the BWT block after them, in blocks of at least 64
+    Interleaved,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum RankStructure {
+    /// The O-table of the index
+    OTable,
+    /// A wavelet tree built from the BWT of the index before timing
+    WaveletTree,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Scheme {
+    /// Backtracking from the end of the read, pruned by the D-table
+    Backtracking,
+    /// Search schemes over edits + 1 pieces, one of which matches exactly
+    Pigeonhole,
+    /// The search schemes of Kucherov et al., for up to 2 edits
+    Kucherov,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Distance {
+    /// Edit distance, with substitutions, insertions and deletions
+    Edit,
+    /// Hamming distance, with substitutions only
+    Hamming,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Report {
+    /// Only the best position
+    Best,
+    /// Every position aligned with the fewest edits
+    AllBest,
+    /// Every position found, or those within --within edits (or penalty) of the best
+    All,
+}
+
+#[derive(Args)]
+pub struct SearchArgs {
+    #[command(flatten)]
+    pub genome: GenomeArgs,
+
+    /// Reads file in FASTQ format, optionally gzip-compressed. `-` reads standard input
+    #[arg(short, long)]
+    pub reads: PathBuf,
+
+    /// Maximum number of edits. 0 uses exact search
+    #[arg(short, long, default_value_t = 0)]
+    pub edits: usize,
+
+    /// Also search for the reverse complement of every read
+    #[arg(long)]
+    pub both_strands: bool,
+
+    /// Edit cost of aligning an N in a read to any base
+    #[arg(long, default_value_t = 1)]
+    pub n_cost: usize,
+
+    /// How to search for reads with edits
+    #[arg(long, value_enum, default_value_t = Scheme::Backtracking)]
+    pub scheme: Scheme,
+
+    /// Which edits are allowed
+    #[arg(long, value_enum, default_value_t = Distance::Edit)]
+    pub distance: Distance,
+
+    /// Which positions of a read to report. Each position is reported once, with
+    /// its alignment of fewest edits
+    #[arg(long, value_enum, default_value_t = Report::All)]
+    pub report: Report,
+
+    /// With --report all, only report positions with at most this many edits more than the best,
+    /// or this much more penalty with --qualities
+    #[arg(long)]
+    pub within: Option<usize>,
+
+    /// Weigh mismatches by the base qualities of the reads, and rank and score
+    /// alignments by the weighted penalty instead of the edits
+    #[arg(long)]
+    pub qualities: bool,
+
+    /// With --qualities, drop alignments with a higher penalty than this
+    #[arg(long, requires = "qualities")]
+    pub max_penalty: Option<usize>,
+
+    /// Check every reported alignment against the reference with a dynamic
+    /// programming alignment, and stop at the first that does not match
+    #[arg(long)]
+    pub verify: bool,
+
+    /// Report at most this many positions per read
+    #[arg(long, value_parser = positive::<usize>)]
+    pub max_hits: Option<usize>,
+
+    /// File to write the results to, instead of stdout
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Number of threads to search for reads on
+    #[arg(short, long, default_value_t = 1, value_parser = positive::<usize>)]
+    pub threads: usize,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum Algorithm {
+    Sais,
+    Skew,
+    NaiveSa,
+    Otable,
+    SampledSa,
+    Approx,
+    ExactBwt,
+    ExactBinary,
+}
+
+#[derive(Args)]
+pub struct BenchArgs {
+    /// The algorithm to time
+    #[arg(value_enum)]
+    pub algorithm: Algorithm,
+
+    #[command(flatten)]
+    pub genome: GenomeArgs,
+
+    /// Reads file in FASTQ format, optionally gzip-compressed, for the search algorithms
+    #[arg(
+        short,
+        long,
+        required_if_eq_any([
+            ("algorithm", "approx"),
+            ("algorithm", "exact-bwt"),
+            ("algorithm", "exact-binary"),
+        ])
+    )]
+    pub reads: Option<PathBuf>,
+
+    /// How many times to repeat the operation
+    #[arg(short, long, default_value_t = 1, value_parser = positive::<u128>)]
+    pub iterations: u128,
+
+    /// Maximum number of edits, for approx
+    #[arg(short, long, default_value_t = 0)]
+    pub edits: usize,
+
+    /// How to search for reads with edits, for approx
+    #[arg(long, value_enum, default_value_t = Scheme::Backtracking)]
+    pub scheme: Scheme,
+
+    /// Which edits are allowed, for approx
+    #[arg(long, value_enum, default_value_t = Distance::Edit)]
+    pub distance: Distance,
+
+    /// Suffix array sampling rate, for sampled-sa
+    #[arg(long, default_value_t = 32, value_parser = positive::<usize>)]
+    pub rate: usize,
+
+    /// Sample the suffix array by rank instead of by text position, for sampled-sa
+    #[arg(long)]
+    pub by_rank: bool,
+
+    /// Number of threads to search for reads on, for approx and exact-bwt
+    #[arg(short, long, default_value_t = 1, value_parser = positive::<usize>)]
+    pub threads: usize,
+
+    /// Structure answering rank queries during the search, for approx and exact-bwt
+    #[arg(long, value_enum, default_value_t = RankStructure::OTable)]
+    pub rank: RankStructure,
+
+    /// Only print the average runtime
+    #[arg(long)]
+    pub no_output: bool,
+}
+
+/// Parses a number that must be at least 1.
+fn positive<T: FromStr + PartialOrd + From<u8>>(s: &str) -> Result<T, String> {
+    match s.parse::<T>() {
+        Ok(n) if n >= T::from(1) => Ok(n),
+        Ok(_) => Err("must be at least 1".to_string()),
+        Err(_) => Err(format!("{} is not a number", s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli_definition() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_parse_map() {
+        let cli = Cli::try_parse_from([
+            "gene_search",
+            "map",
+            "--genome",
+            "genome.fa",
+            "-r",
+            "reads.fq",
+            "--edits",
+            "2",
+            "--both-strands",
+            "--threads",
+            "4",
+        ])
+        .unwrap();
+
+        match cli.command {
+            Command::Map(args) => {
+                assert_eq!(args.genome.genome, Some(PathBuf::from("genome.fa")));
+                assert_eq!(args.genome.index, None);
+                assert_eq!(args.genome.spacing, 10);
+                assert_eq!(args.reads, PathBuf::from("reads.fq"));
+                assert_eq!(args.edits, 2);
+                assert!(args.both_strands);
+                assert_eq!(args.threads, 4);
+            }
+            _ => panic!("expected the map subcommand"),
+        }
+    }
+
+    #[test]
+    fn test_bench_search_requires_reads() {
+        let without_reads = ["gene_search", "bench", "approx", "-g", "genome.fa"];
+        assert!(Cli::try_parse_from(without_reads).is_err());
+
+        let construction = ["gene_search", "bench", "sais", "-g", "genome.fa"];
+        assert!(Cli::try_parse_from(construction).is_ok());
+    }
+
+    #[test]
+    fn test_genome_or_index_is_required() {
+        assert!(Cli::try_parse_from(["gene_search", "stats"]).is_err());
+
+        let cli = Cli::try_parse_from(["gene_search", "stats", "-x", "genome.idx"]).unwrap();
+        match cli.command {
+            Command::Stats(args) => {
+                assert_eq!(args.genome, None);
+                assert_eq!(args.index, Some(PathBuf::from("genome.idx")));
+            }
+            _ => panic!("expected the stats subcommand"),
+        }
+    }
+
+    #[test]
+    fn test_zero_spacing_is_rejected() {
+        let args = ["gene_search", "index", "-g", "genome.fa", "--spacing", "0"];
+        assert!(Cli::try_parse_from(args).is_err());
+    }
+
+    #[test]
+    fn test_zero_max_hits_is_rejected() {
+        let args = |max_hits| {
+            let reads = ["-r", "reads.fq", "--max-hits", max_hits];
+            Cli::try_parse_from(
+                ["gene_search", "map", "-g", "genome.fa"]
+                    .iter()
+                    .chain(&reads),
+            )
+        };
+        assert!(args("0").is_err());
+        assert!(args("1").is_ok());
+    }
+}
@@ -0,0 +1,89 @@
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug)]
+pub enum GeneSearchError {
+    /// Reading or writing a file failed.
+    Io(std::io::Error),
+    /// A genome, reads file or index could not be parsed.
+    Parse(String),
+    /// An index file was written by an incompatible version.
+    FormatVersion { found: u32, expected: u32 },
+    /// An index file does not match the genome or settings it is used with.
+    IndexMismatch(String),
+    /// An argument is outside the values the operation accepts.
+    InvalidArgument(String),
+    /// A reported alignment does not match the reference it was found at.
+    Verification(String),
+}
+
+pub type Result<T> = std::result::Result<T, GeneSearchError>;
+
+impl Display for GeneSearchError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GeneSearchError::Io(e) => write!(f, "{}", e),
+            GeneSearchError::Parse(message) => write!(f, "{}", message),
+            GeneSearchError::FormatVersion { found, expected } => write!(
+                f,
+                "index has format version {}, but version {} is required. Rebuild the index",
+                found, expected
+            ),
+            GeneSearchError::IndexMismatch(message) => write!(f, "{}", message),
+            GeneSearchError::InvalidArgument(message) => write!(f, "{}", message),
+            GeneSearchError::Verification(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for GeneSearchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GeneSearchError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for GeneSearchError {
+    fn from(e: std::io::Error) -> Self {
+        GeneSearchError::Io(e)
+    }
+}
+
+impl From<seq_io::fastq::Error> for GeneSearchError {
+    fn from(e: seq_io::fastq::Error) -> Self {
+        match e {
+            seq_io::fastq::Error::Io(e) => GeneSearchError::Io(e),
+            e => GeneSearchError::Parse(format!("malformed FASTQ: {}", e)),
+        }
+    }
+}
+
+/// Adds the path of the file being read or written to I/O errors.
+pub fn with_path<T>(result: Result<T>, path: &std::path::Path) -> Result<T> {
+    result.map_err(|e| match e {
+        GeneSearchError::Io(e) => GeneSearchError::Io(std::io::Error::new(
+            e.kind(),
+            format!("{}: {}", path.display(), e),
+        )),
+        GeneSearchError::Parse(message) => {
+            GeneSearchError::Parse(format!("{}: {}", path.display(), message))
+        }
+        e => e,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reference::Reference;
+    use std::path::Path;
+
+    #[test]
+    fn test_missing_file_names_path() {
+        let error = Reference::from_path(Path::new("resources/genomes/missing.fa")).unwrap_err();
+
+        assert!(matches!(error, GeneSearchError::Io(_)));
+        assert!(error.to_string().contains("resources/genomes/missing.fa"));
+    }
+}
@@ -1,24 +1,44 @@
-use crate::o_table::OTable;
-use std::{
-    cmp::{min, Ordering},
-    usize,
-};
+use crate::rank::Rank;
+use crate::util::{reverse_complement, Strand, WILDCARD};
+use std::cmp::{min, Ordering};
 
 /// Backwards search with Burrows-Wheeler Transform. Inspired by "String Algorithms in C".
-pub fn bwt_search(query: &[u8], o_table: &OTable, c_table: &[usize]) -> (usize, usize) {
-    let (_, cols) = o_table.shape();
-
+pub fn bwt_search<R: Rank + ?Sized>(
+    query: &[u8],
+    o_table: &R,
+    c_table: &[usize],
+) -> (usize, usize) {
     let mut start = 0;
-    let mut end = cols - 1;
+    let mut end = o_table.len();
 
     for &a in query.iter().rev() {
-        start = c_table[a as usize] + o_table.get(a, start);
-        end = c_table[a as usize] + o_table.get(a, end);
+        if a == WILDCARD {
+            // Ambiguous bases never match exactly
+            return (1, 0);
+        }
+        start = c_table[a as usize] + o_table.rank(a, start);
+        end = c_table[a as usize] + o_table.rank(a, end);
     }
 
     (start, end - 1)
 }
 
+/// Backwards search for the query, and optionally for its reverse complement.
+/// Each interval is tagged with the strand it was found on.
+pub fn bwt_search_strands<R: Rank + ?Sized>(
+    query: &[u8],
+    o_table: &R,
+    c_table: &[usize],
+    both_strands: bool,
+) -> Vec<(Strand, (usize, usize))> {
+    let mut results = vec![(Strand::Forward, bwt_search(query, o_table, c_table))];
+    if both_strands {
+        let reverse = reverse_complement(query);
+        results.push((Strand::Reverse, bwt_search(&reverse, o_table, c_table)));
+    }
+    results
+}
+
 /// Exact search based on binary search through the suffix array
 pub fn exact_binary_search(reference: &[u8], suffix_array: &[usize], query: &[u8]) -> usize {
     let mut low = 0;
@@ -46,6 +66,7 @@ pub fn exact_binary_search(reference: &[u8], suffix_array: &[usize], query: &[u8
 mod tests {
     use super::*;
     use crate::{
+        o_table::OTable,
         suffix_array_construction::construct_suffix_array_naive,
         table_gen::generate_c_table,
         util::{remap_query, remap_reference},
@@ -53,11 +74,11 @@ mod tests {
 
     #[test]
     fn test_bwt_search_1_match() {
-        let reference = remap_reference("CATTGA");
+        let reference = remap_reference("CATTGA").unwrap();
         let suffix_array = construct_suffix_array_naive(&reference);
         let o_table = OTable::new(&reference, &suffix_array, 10);
         let c_table = generate_c_table(&reference);
-        let query = remap_query("ATT");
+        let query = remap_query("ATT").unwrap();
         let search_result = bwt_search(&query, &o_table, &c_table);
 
         assert_eq!((2, 2), search_result);
@@ -65,11 +86,11 @@ mod tests {
 
     #[test]
     fn test_bwt_search_banana() {
-        let reference = remap_reference("CAGAGA");
+        let reference = remap_reference("CAGAGA").unwrap();
         let suffix_array = construct_suffix_array_naive(&reference);
         let o_table = OTable::new(&reference, &suffix_array, 10);
         let c_table = generate_c_table(&reference);
-        let query = remap_query("AGA");
+        let query = remap_query("AGA").unwrap();
         let search_result = bwt_search(&query, &o_table, &c_table);
 
         assert_eq!((2, 3), search_result);
@@ -77,11 +98,11 @@ mod tests {
 
     #[test]
     fn test_bwt_search_2_matches() {
-        let reference = remap_reference("AGAGA");
+        let reference = remap_reference("AGAGA").unwrap();
         let suffix_array = construct_suffix_array_naive(&reference);
         let o_table = OTable::new(&reference, &suffix_array, 10);
         let c_table = generate_c_table(&reference);
-        let query = remap_query("AGA");
+        let query = remap_query("AGA").unwrap();
         let search_result = bwt_search(&query, &o_table, &c_table);
 
         assert_eq!((2, 3), search_result);
@@ -89,23 +110,52 @@ mod tests {
 
     #[test]
     fn test_bwt_search_0_matches() {
-        let reference = remap_reference("AGAGA");
+        let reference = remap_reference("AGAGA").unwrap();
+        let suffix_array = construct_suffix_array_naive(&reference);
+        let o_table = OTable::new(&reference, &suffix_array, 10);
+        let c_table = generate_c_table(&reference);
+        let query = remap_query("ACA").unwrap();
+        let search_result = bwt_search(&query, &o_table, &c_table);
+
+        assert!(search_result.0 > search_result.1);
+    }
+
+    #[test]
+    fn test_bwt_search_wildcard() {
+        let reference = remap_reference("AGAGA").unwrap();
         let suffix_array = construct_suffix_array_naive(&reference);
         let o_table = OTable::new(&reference, &suffix_array, 10);
         let c_table = generate_c_table(&reference);
-        let query = remap_query("ACA");
+        let query = remap_query("ANA").unwrap();
         let search_result = bwt_search(&query, &o_table, &c_table);
 
         assert!(search_result.0 > search_result.1);
     }
 
+    #[test]
+    fn test_bwt_search_strands() {
+        let reference = remap_reference("CATTGA").unwrap();
+        let suffix_array = construct_suffix_array_naive(&reference);
+        let o_table = OTable::new(&reference, &suffix_array, 10);
+        let c_table = generate_c_table(&reference);
+        // The reverse complement of AAT is ATT
+        let query = remap_query("AAT").unwrap();
+
+        let forward_only = bwt_search_strands(&query, &o_table, &c_table, false);
+        assert_eq!(forward_only.len(), 1);
+        assert!(forward_only[0].1 .0 > forward_only[0].1 .1);
+
+        let both = bwt_search_strands(&query, &o_table, &c_table, true);
+        assert_eq!(both[1], (Strand::Reverse, (2, 2)));
+    }
+
     #[test]
     fn test_bwt_search_query_longer_than_reference() {
-        let reference = remap_reference("AGAGA");
+        let reference = remap_reference("AGAGA").unwrap();
         let suffix_array = construct_suffix_array_naive(&reference);
         let o_table = OTable::new(&reference, &suffix_array, 10);
         let c_table = generate_c_table(&reference);
-        let query = remap_query("ACAAGAGAGA");
+        let query = remap_query("ACAAGAGAGA").unwrap();
         let search_result = bwt_search(&query, &o_table, &c_table);
 
         assert!(search_result.0 > search_result.1);
@@ -0,0 +1,801 @@
+use crate::approx_search::{approx_search_strands, ApproxSearchParams, EditModel};
+use crate::batch::map_in_order;
+use crate::bidirectional::BidirectionalIndex;
+use crate::error::{with_path, GeneSearchError, Result};
+use crate::exact_search::bwt_search_strands;
+use crate::index_file::{
+    content_hash, write_bytes, write_lines, write_usizes, write_words, IndexHeader, SectionReader,
+};
+use crate::locate::locate_exact;
+use crate::o_table::{OTable, OTableLayout, OTableParts};
+use crate::packed_bwt::{InterleavedBwt, PackedBwt, BLOCK_SYMBOLS};
+use crate::rank::Rank;
+use crate::reference::{Reference, ReferenceRecord};
+use crate::sais::suffix_array_induced_sort;
+use crate::search_scheme::{scheme_search_strands, SearchScheme};
+use crate::storage::{CacheLine, Plain, Storage};
+use crate::table_gen::generate_c_table;
+use crate::types::{CTable, StrandAlignments};
+use crate::util::{remap_query, remap_reference_with_policy, AmbiguityPolicy, Strand};
+use crate::ALPHABET;
+use memmap2::Mmap;
+use std::fs::File;
+use std::io::{BufWriter, Cursor, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// An FM-index over a reference, with the tables of the reversed reference that
+/// approximate search uses for its D-table and that make the index bidirectional.
+///
+/// An index opened with `open` reads its tables straight from the memory-mapped
+/// index file, so processes using the same index share one copy in the page cache.
+pub struct FmIndex {
+    reference: Reference,
+    genome: Storage<u8>,
+    suffix_array: Storage<usize>,
+    o_table: OTable,
+    c_table: CTable,
+    reverse_o_table: OTable,
+}
+
+impl FmIndex {
+    /// Remaps a reference according to `policy` and builds its index, sampling
+    /// the O-tables every `spacing` positions.
+    pub fn new(reference: Reference, spacing: usize, policy: AmbiguityPolicy) -> Result<Self> {
+        FmIndex::with_layout(reference, spacing, OTableLayout::Separate, policy)
+    }
+
+    /// Builds an index like `new`, laying out the O-tables as `layout`.
+    pub fn with_layout(
+        reference: Reference,
+        spacing: usize,
+        layout: OTableLayout,
+        policy: AmbiguityPolicy,
+    ) -> Result<Self> {
+        if spacing == 0 {
+            return Err(GeneSearchError::InvalidArgument(
+                "the O-table spacing must be at least 1".to_string(),
+            ));
+        }
+
+        let genome: Vec<u8> = remap_reference_with_policy(reference.text()?, policy)?.sequence;
+        let suffix_array = suffix_array_induced_sort(&genome);
+        let o_table = OTable::with_layout(&genome, &suffix_array, spacing, layout);
+        let c_table = generate_c_table(&genome);
+
+        // Reverse the remapped genome, so randomized bases stay the same
+        let mut reverse_genome = genome[..genome.len() - 1].to_vec();
+        reverse_genome.reverse();
+        reverse_genome.push(0);
+        let reverse_suffix_array = suffix_array_induced_sort(&reverse_genome);
+        let reverse_o_table =
+            OTable::with_layout(&reverse_genome, &reverse_suffix_array, spacing, layout);
+
+        Ok(FmIndex {
+            reference,
+            genome: genome.into(),
+            suffix_array: suffix_array.into(),
+            o_table,
+            c_table,
+            reverse_o_table,
+        })
+    }
+
+    /// Reads a FASTA file and builds its index.
+    pub fn from_path(path: &Path, spacing: usize, policy: AmbiguityPolicy) -> Result<Self> {
+        FmIndex::new(Reference::from_path(path)?, spacing, policy)
+    }
+
+    /// Writes the index to a single file: an `IndexHeader` followed by the
+    /// records of the reference and every table.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let written = File::create(path)
+            .map_err(GeneSearchError::from)
+            .and_then(|file| {
+                let mut writer = BufWriter::new(file);
+                self.write_to(&mut writer)?;
+                writer.flush()?;
+                Ok(())
+            });
+        with_path(written, path)
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let o_table = &self.o_table;
+        IndexHeader::new(&self.genome, o_table.spacing(), o_table.layout()).write(writer)?;
+
+        let records = &self.reference.records;
+        let names: Vec<&str> = records.iter().map(|r| r.name.as_str()).collect();
+        let extents: Vec<usize> = records.iter().flat_map(|r| [r.offset, r.len]).collect();
+        write_bytes(writer, names.join("\n").as_bytes())?;
+        write_usizes(writer, &extents)?;
+        write_bytes(writer, &self.reference.sequence)?;
+
+        write_bytes(writer, &self.genome)?;
+        write_usizes(writer, &self.suffix_array)?;
+        write_usizes(writer, &self.c_table)?;
+        for o_table in [&self.o_table, &self.reverse_o_table] {
+            match o_table.parts() {
+                OTableParts::Separate { array, bwt } => {
+                    let (_, symbols, sentinels) = bwt.parts();
+                    write_usizes(writer, array)?;
+                    write_words(writer, symbols)?;
+                    write_words(writer, sentinels)?;
+                }
+                OTableParts::Interleaved(bwt) => write_lines(writer, bwt.parts().2)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads an index written by `save` onto the heap.
+    pub fn load(path: &Path) -> Result<Self> {
+        with_path(FmIndex::read_file(path, None, false), path)
+    }
+
+    /// Reads an index written by `save` onto the heap, failing with
+    /// `IndexMismatch` unless it was built from `genome` with the given O-table
+    /// settings. Only the header is read before the check.
+    pub fn load_matching(
+        path: &Path,
+        genome: &[u8],
+        spacing: usize,
+        layout: OTableLayout,
+    ) -> Result<Self> {
+        with_path(
+            FmIndex::read_file(path, Some((genome, spacing, layout)), false),
+            path,
+        )
+    }
+
+    /// Memory-maps an index written by `save`. Tables are read from the file as
+    /// they are used, so opening takes the same time for any genome size.
+    ///
+    /// Unlike `load`, the stored genome is not checked against the content hash
+    /// of the header, as that would read all of it. The file must not be modified
+    /// while the index is open.
+    pub fn open(path: &Path) -> Result<Self> {
+        with_path(FmIndex::read_file(path, None, true), path)
+    }
+
+    /// Memory-maps an index like `open`, checking it like `load_matching`.
+    pub fn open_matching(
+        path: &Path,
+        genome: &[u8],
+        spacing: usize,
+        layout: OTableLayout,
+    ) -> Result<Self> {
+        with_path(
+            FmIndex::read_file(path, Some((genome, spacing, layout)), true),
+            path,
+        )
+    }
+
+    fn read_file(
+        path: &Path,
+        expected: Option<(&[u8], usize, OTableLayout)>,
+        keep_mapped: bool,
+    ) -> Result<Self> {
+        let file = File::open(path)?;
+        // Safety: the map is never written through, and `open` documents that the
+        // file must not change while it is mapped
+        let map = Arc::new(unsafe { Mmap::map(&file)? });
+
+        let mut cursor = Cursor::new(&map[..]);
+        let header = IndexHeader::read(&mut cursor)?;
+        if let Some((genome, spacing, layout)) = expected {
+            header.check_matches(genome, spacing, layout)?;
+        }
+        let index = FmIndex::read_sections(&map, cursor.position() as usize, &header)?;
+        if keep_mapped {
+            return Ok(index);
+        }
+
+        // Check the stored genome against the header, so a corrupted file is not
+        // mistaken for an index of a different genome
+        if content_hash(&index.genome) != header.content_hash {
+            return Err(GeneSearchError::Parse(
+                "index is corrupt: the genome does not match the header".to_string(),
+            ));
+        }
+        Ok(index.into_owned())
+    }
+
+    fn read_sections(map: &Arc<Mmap>, position: usize, header: &IndexHeader) -> Result<Self> {
+        let mut sections = SectionReader::new(map, position);
+        let names = std::str::from_utf8(sections.next_bytes()?)
+            .map_err(|_| GeneSearchError::Parse("record names are not UTF-8".to_string()))?;
+        let extents: Storage<usize> = next_table(map, &mut sections)?;
+        let sequence: Storage<u8> = next_table(map, &mut sections)?;
+        let records = names
+            .split('\n')
+            .zip(extents.chunks_exact(2))
+            .map(|(name, extent)| ReferenceRecord {
+                name: name.to_string(),
+                offset: extent[0],
+                len: extent[1],
+            })
+            .collect();
+
+        let genome: Storage<u8> = next_table(map, &mut sections)?;
+        let suffix_array: Storage<usize> = next_table(map, &mut sections)?;
+        let c_table: Storage<usize> = next_table(map, &mut sections)?;
+        let wrong_length =
+            || GeneSearchError::Parse("index is corrupt: the BWT has the wrong length".to_string());
+        let mut next_o_table = || -> Result<OTable> {
+            if header.layout == OTableLayout::Interleaved {
+                let block_len = header.spacing.div_ceil(BLOCK_SYMBOLS) * BLOCK_SYMBOLS;
+                let lines: Storage<CacheLine> = next_table(map, &mut sections)?;
+                if lines.len() != InterleavedBwt::lines_len(header.length, block_len) {
+                    return Err(wrong_length());
+                }
+                let bwt = InterleavedBwt::from_parts(header.length, block_len, lines);
+                return Ok(OTable::from_parts(
+                    header.spacing,
+                    OTableParts::Interleaved(bwt),
+                ));
+            }
+
+            let array = next_table(map, &mut sections)?;
+            let symbols: Storage<u64> = next_table(map, &mut sections)?;
+            let sentinels: Storage<u64> = next_table(map, &mut sections)?;
+            if symbols.len() != header.length.div_ceil(32)
+                || sentinels.len() != header.length.div_ceil(64)
+            {
+                return Err(wrong_length());
+            }
+            let bwt = PackedBwt::from_parts(header.length, symbols, sentinels);
+            Ok(OTable::from_parts(
+                header.spacing,
+                OTableParts::Separate { array, bwt },
+            ))
+        };
+        let o_table = next_o_table()?;
+        let reverse_o_table = next_o_table()?;
+
+        if genome.len() != header.length {
+            return Err(GeneSearchError::Parse(
+                "index is corrupt: the genome does not match the header".to_string(),
+            ));
+        }
+        if suffix_array.len() != genome.len() {
+            return Err(GeneSearchError::Parse(
+                "index is corrupt: the suffix array has the wrong length".to_string(),
+            ));
+        }
+        if c_table.len() != ALPHABET.len() {
+            return Err(GeneSearchError::Parse(
+                "index is corrupt: the C-table has the wrong length".to_string(),
+            ));
+        }
+
+        Ok(FmIndex {
+            reference: Reference { sequence, records },
+            genome,
+            suffix_array,
+            o_table,
+            c_table: c_table.into_owned(),
+            reverse_o_table,
+        })
+    }
+
+    /// Copies every mapped table onto the heap.
+    fn into_owned(self) -> Self {
+        let owned_o_table = |o_table: OTable| {
+            let spacing = o_table.spacing();
+            OTable::from_parts(spacing, o_table.into_parts().into_owned())
+        };
+        let reference = Reference {
+            sequence: self.reference.sequence.into_owned().into(),
+            records: self.reference.records,
+        };
+        FmIndex {
+            reference,
+            genome: self.genome.into_owned().into(),
+            suffix_array: self.suffix_array.into_owned().into(),
+            o_table: owned_o_table(self.o_table),
+            c_table: self.c_table,
+            reverse_o_table: owned_o_table(self.reverse_o_table),
+        }
+    }
+
+    /// Whether the tables are read from a memory-mapped index file.
+    pub fn is_mapped(&self) -> bool {
+        self.suffix_array.is_mapped()
+    }
+
+    pub fn reference(&self) -> &Reference {
+        &self.reference
+    }
+
+    /// The remapped reference, ending with the sentinel.
+    pub fn genome(&self) -> &[u8] {
+        &self.genome
+    }
+
+    pub fn suffix_array(&self) -> &[usize] {
+        &self.suffix_array
+    }
+
+    pub fn o_table(&self) -> &OTable {
+        &self.o_table
+    }
+
+    pub fn c_table(&self) -> &[usize] {
+        &self.c_table
+    }
+
+    pub fn reverse_o_table(&self) -> &OTable {
+        &self.reverse_o_table
+    }
+
+    /// The forward and reverse O-tables as a bidirectional index, for extending
+    /// matches at both ends.
+    pub fn bidirectional(&self) -> BidirectionalIndex<'_> {
+        BidirectionalIndex::new(&self.o_table, &self.reverse_o_table, &self.c_table)
+    }
+
+    /// Searches for exact matches of a read, returning the inclusive SA interval
+    /// found on each strand. Fails if the read holds a character that is not a
+    /// base or ambiguity code.
+    pub fn search_exact(
+        &self,
+        query: &str,
+        both_strands: bool,
+    ) -> Result<Vec<(Strand, (usize, usize))>> {
+        let query = remap_query(query)?;
+        Ok(bwt_search_strands(
+            &query,
+            &self.o_table,
+            &self.c_table,
+            both_strands,
+        ))
+    }
+
+    /// Counts the exact matches of a read without locating them.
+    pub fn count(&self, query: &str, both_strands: bool) -> Result<usize> {
+        Ok(self
+            .search_exact(query, both_strands)?
+            .iter()
+            .map(|&(_, (start, end))| (end + 1).saturating_sub(start))
+            .sum())
+    }
+
+    /// Locates the exact matches of a read, sorted by strand and position.
+    pub fn locate(
+        &self,
+        query: &str,
+        both_strands: bool,
+        max_hits: Option<usize>,
+    ) -> Result<Vec<(Strand, usize)>> {
+        let mut hits: Vec<(Strand, usize)> = self
+            .search_exact(query, both_strands)?
+            .into_iter()
+            .flat_map(|(strand, interval)| {
+                locate_exact(self.suffix_array(), interval, None)
+                    .into_iter()
+                    .map(move |position| (strand, position))
+            })
+            .collect();
+        hits.sort_unstable();
+        if let Some(max_hits) = max_hits {
+            hits.truncate(max_hits);
+        }
+        Ok(hits)
+    }
+
+    /// The parameters for approximate search of an already remapped query, with
+    /// insertions and deletions allowed.
+    pub fn approx_params<'a>(
+        &'a self,
+        query: &'a [u8],
+        edits: usize,
+        wildcard_cost: usize,
+    ) -> ApproxSearchParams<'a> {
+        self.approx_params_over(
+            &self.o_table,
+            &self.reverse_o_table,
+            query,
+            edits,
+            wildcard_cost,
+        )
+    }
+
+    /// Like `approx_params`, but searching over other rank structures of the
+    /// forward and reverse BWT, such as wavelet trees.
+    pub fn approx_params_over<'a, R: Rank + ?Sized>(
+        &'a self,
+        o_table: &'a R,
+        rev_o_table: &'a R,
+        query: &'a [u8],
+        edits: usize,
+        wildcard_cost: usize,
+    ) -> ApproxSearchParams<'a, R> {
+        ApproxSearchParams {
+            reference: &self.genome,
+            query,
+            o_table,
+            c_table: &self.c_table,
+            rev_o_table,
+            edits,
+            edit_model: EditModel::Levenshtein,
+            wildcard_cost,
+            scoring: None,
+        }
+    }
+
+    /// Searches for matches of a read with at most `edits` edits of the kinds
+    /// `edit_model` allows. Results are `(strand, left, right, cigar, edits)` with
+    /// half-open SA intervals.
+    pub fn approx_search(
+        &self,
+        query: &str,
+        edits: usize,
+        edit_model: EditModel,
+        wildcard_cost: usize,
+        both_strands: bool,
+    ) -> Result<StrandAlignments> {
+        let query = remap_query(query)?;
+        let params = ApproxSearchParams {
+            edit_model,
+            ..self.approx_params(&query, edits, wildcard_cost)
+        };
+        Ok(approx_search_strands(params, both_strands))
+    }
+
+    /// Searches for matches of a read like `approx_search`, driven by a search
+    /// scheme for `scheme.edits()` edits.
+    pub fn scheme_search(
+        &self,
+        query: &str,
+        scheme: &SearchScheme,
+        edit_model: EditModel,
+        wildcard_cost: usize,
+        both_strands: bool,
+    ) -> Result<StrandAlignments> {
+        let query = remap_query(query)?;
+        let params = ApproxSearchParams {
+            edit_model,
+            ..self.approx_params(&query, scheme.edits(), wildcard_cost)
+        };
+        Ok(scheme_search_strands(params, scheme, both_strands))
+    }
+
+    /// Runs `search` for every read on `threads` threads sharing this index,
+    /// returning the results in the order of the reads.
+    pub fn map_reads<Q, T, F>(&self, reads: &[Q], threads: usize, search: F) -> Vec<T>
+    where
+        Q: Sync,
+        T: Send,
+        F: Fn(&FmIndex, &Q) -> T + Sync,
+    {
+        map_in_order(reads, threads, |read| search(self, read))
+    }
+
+    /// Searches for exact matches of remapped reads, such as those returned by
+    /// `read_and_remap_reads`, on `threads` threads.
+    pub fn search_exact_batch(
+        &self,
+        queries: &[Vec<u8>],
+        both_strands: bool,
+        threads: usize,
+    ) -> Vec<Vec<(Strand, (usize, usize))>> {
+        self.map_reads(queries, threads, |index, query| {
+            bwt_search_strands(query, &index.o_table, &index.c_table, both_strands)
+        })
+    }
+
+    /// Searches for matches of remapped reads with at most `edits` edits on
+    /// `threads` threads.
+    pub fn approx_search_batch(
+        &self,
+        queries: &[Vec<u8>],
+        edits: usize,
+        edit_model: EditModel,
+        wildcard_cost: usize,
+        both_strands: bool,
+        threads: usize,
+    ) -> Vec<StrandAlignments> {
+        self.map_reads(queries, threads, |index, query| {
+            let params = ApproxSearchParams {
+                edit_model,
+                ..index.approx_params(query, edits, wildcard_cost)
+            };
+            approx_search_strands(params, both_strands)
+        })
+    }
+}
+
+/// Uses the next section of a mapped index file as a table.
+fn next_table<T: Plain>(map: &Arc<Mmap>, sections: &mut SectionReader) -> Result<Storage<T>> {
+    let (offset, len) = sections.next_section(std::mem::size_of::<T>())?;
+    Ok(Storage::mapped(map, offset, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn small_index() -> FmIndex {
+        let fasta = ">chr1\nACGTACGTTTAG\n>chr2\nGGTACGNNAC\n";
+        let reference = Reference::from_fasta(Cursor::new(fasta), "unnamed").unwrap();
+        FmIndex::new(reference, 3, AmbiguityPolicy::NonMatching).unwrap()
+    }
+
+    #[test]
+    fn test_count_and_locate() {
+        let index = small_index();
+
+        assert_eq!(index.count("TACG", false).unwrap(), 2);
+        let hits = index.locate("TACG", false, None).unwrap();
+        let positions: Vec<usize> = hits.iter().map(|&(_, p)| p).collect();
+        assert_eq!(
+            index.reference().resolve_all(&positions),
+            vec![("chr1", 3), ("chr2", 2)]
+        );
+
+        assert_eq!(index.locate("TACG", false, Some(1)).unwrap().len(), 1);
+        assert_eq!(index.count("CGTA", true).unwrap(), 3);
+
+        let bidirectional = index.bidirectional();
+        let seed = bidirectional.extend_left(bidirectional.full(), 2);
+        let interval = bidirectional.extend_right(bidirectional.extend_left(seed, 1), 3);
+        assert_eq!(bidirectional.extend_left(interval, 4).len, 2);
+    }
+
+    #[test]
+    fn test_query_cannot_match_across_records() {
+        let fasta = ">a\nACGT\n>b\nTTGA\n";
+        let reference = Reference::from_fasta(Cursor::new(fasta), "unnamed").unwrap();
+        let index = FmIndex::new(reference, 3, AmbiguityPolicy::NonMatching).unwrap();
+
+        assert!(matches!(
+            index.count("GT$TT", false),
+            Err(GeneSearchError::Parse(_))
+        ));
+        assert!(index.locate("GT$TT", true, None).is_err());
+        assert!(index
+            .approx_search("GT$TT", 1, EditModel::Levenshtein, 1, false)
+            .is_err());
+        assert_eq!(index.count("GTTT", false).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_queries_with_unknown_characters_fail() {
+        let index = small_index();
+        assert!(matches!(
+            index.count("AC-GT", false),
+            Err(GeneSearchError::Parse(_))
+        ));
+        assert!(index
+            .approx_search("ACGU", 1, EditModel::Levenshtein, 1, false)
+            .is_err());
+    }
+
+    #[test]
+    fn test_approx_search() {
+        let index = small_index();
+
+        let results = index
+            .approx_search("TTTA", 1, EditModel::Levenshtein, 1, false)
+            .unwrap();
+        assert_eq!(
+            index
+                .scheme_search(
+                    "TTTA",
+                    &SearchScheme::pigeonhole(1),
+                    EditModel::Levenshtein,
+                    1,
+                    false
+                )
+                .unwrap(),
+            results
+        );
+        assert!(results
+            .iter()
+            .any(|(strand, _, _, cigar, edits)| *strand == Strand::Forward
+                && cigar.to_string() == "4="
+                && *edits == 0));
+        assert!(results.iter().all(|result| result.4 <= 1));
+
+        let substitutions: StrandAlignments = results
+            .into_iter()
+            .filter(|(_, _, _, cigar, _)| !cigar.has_indels())
+            .collect();
+        assert_eq!(
+            index
+                .approx_search("TTTA", 1, EditModel::Hamming, 1, false)
+                .unwrap(),
+            substitutions
+        );
+    }
+
+    #[test]
+    fn test_batch_search_keeps_order() {
+        let index = small_index();
+        let reads = ["TACG", "GGTA", "CCCC", "ACGT", "TTTA"];
+        let queries: Vec<Vec<u8>> = reads
+            .iter()
+            .map(|read| remap_query(read).unwrap())
+            .collect();
+
+        let exact = index.search_exact_batch(&queries, true, 3);
+        let approx = index.approx_search_batch(&queries, 1, EditModel::Levenshtein, 1, true, 3);
+        for (i, read) in reads.iter().enumerate() {
+            assert_eq!(exact[i], index.search_exact(read, true).unwrap());
+            assert_eq!(
+                approx[i],
+                index
+                    .approx_search(read, 1, EditModel::Levenshtein, 1, true)
+                    .unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let index = small_index();
+        let path = std::env::temp_dir().join("gene_search_test_save_and_load.idx");
+        index.save(&path).unwrap();
+        let loaded = FmIndex::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(!loaded.is_mapped());
+        assert_eq!(loaded.reference(), index.reference());
+        assert_eq!(loaded.genome(), index.genome());
+        assert_eq!(loaded.suffix_array(), index.suffix_array());
+        assert_eq!(loaded.c_table(), index.c_table());
+        for (loaded, built) in [
+            (loaded.o_table(), index.o_table()),
+            (loaded.reverse_o_table(), index.reverse_o_table()),
+        ] {
+            match (loaded.parts(), built.parts()) {
+                (
+                    OTableParts::Separate { array, bwt },
+                    OTableParts::Separate {
+                        array: built_array,
+                        bwt: built_bwt,
+                    },
+                ) => {
+                    assert_eq!(&array[..], &built_array[..]);
+                    assert_eq!(bwt.parts(), built_bwt.parts());
+                }
+                _ => panic!("expected the separate layout"),
+            }
+        }
+        assert_eq!(
+            loaded.locate("GTA", true, None).unwrap(),
+            index.locate("GTA", true, None).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_open_mapped() {
+        let index = small_index();
+        let path = std::env::temp_dir().join("gene_search_test_open_mapped.idx");
+        index.save(&path).unwrap();
+        let opened = FmIndex::open(&path).unwrap();
+        let other_spacing =
+            FmIndex::open_matching(&path, index.genome(), 4, OTableLayout::Separate);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(opened.is_mapped(), cfg!(target_pointer_width = "64"));
+        assert!(!index.is_mapped());
+        // The reference is not copied out of the file either
+        assert!(opened.reference().sequence.is_mapped());
+        assert_eq!(opened.reference(), index.reference());
+        assert_eq!(opened.suffix_array(), index.suffix_array());
+        assert_eq!(opened.c_table(), index.c_table());
+        assert_eq!(
+            opened.locate("GTA", true, None).unwrap(),
+            index.locate("GTA", true, None).unwrap()
+        );
+        assert_eq!(
+            opened
+                .approx_search("TTTA", 1, EditModel::Levenshtein, 1, true)
+                .unwrap(),
+            index
+                .approx_search("TTTA", 1, EditModel::Levenshtein, 1, true)
+                .unwrap()
+        );
+        assert!(matches!(
+            other_spacing,
+            Err(GeneSearchError::IndexMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_interleaved_layout() {
+        let separate = small_index();
+        let reference = separate.reference().clone();
+        let policy = AmbiguityPolicy::NonMatching;
+        let index = FmIndex::with_layout(reference, 3, OTableLayout::Interleaved, policy).unwrap();
+        let path = std::env::temp_dir().join("gene_search_test_interleaved.idx");
+        index.save(&path).unwrap();
+        let opened = FmIndex::open_matching(&path, index.genome(), 3, OTableLayout::Interleaved);
+        let other_layout = FmIndex::load_matching(&path, index.genome(), 3, OTableLayout::Separate);
+        std::fs::remove_file(&path).unwrap();
+
+        let opened = opened.unwrap();
+        assert_eq!(opened.o_table().layout(), OTableLayout::Interleaved);
+        if let OTableParts::Interleaved(bwt) = opened.o_table().parts() {
+            assert_eq!(bwt.is_mapped(), cfg!(target_endian = "little"));
+            assert_eq!(bwt.parts().2.as_ptr() as usize % 64, 0);
+        }
+        for index in [&index, &opened] {
+            assert_eq!(
+                index.locate("GTA", true, None).unwrap(),
+                separate.locate("GTA", true, None).unwrap()
+            );
+            assert_eq!(
+                index
+                    .approx_search("TTTA", 1, EditModel::Levenshtein, 1, true)
+                    .unwrap(),
+                separate
+                    .approx_search("TTTA", 1, EditModel::Levenshtein, 1, true)
+                    .unwrap()
+            );
+        }
+        assert!(matches!(
+            other_layout,
+            Err(GeneSearchError::IndexMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_load_rejects_other_versions() {
+        let index = small_index();
+        let path = std::env::temp_dir().join("gene_search_test_other_version.idx");
+        index.save(&path).unwrap();
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[8] += 1;
+        std::fs::write(&path, bytes).unwrap();
+        let result = FmIndex::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(
+            result,
+            Err(GeneSearchError::FormatVersion { found, expected }) if found == expected + 1
+        ));
+    }
+
+    #[test]
+    fn test_load_matching() {
+        let index = small_index();
+        let path = std::env::temp_dir().join("gene_search_test_load_matching.idx");
+        index.save(&path).unwrap();
+
+        let matching = FmIndex::load_matching(&path, index.genome(), 3, OTableLayout::Separate);
+        let mut changed = index.genome().to_vec();
+        changed[0] = 4;
+        let other_genome = FmIndex::load_matching(&path, &changed, 3, OTableLayout::Separate);
+        let other_spacing =
+            FmIndex::load_matching(&path, index.genome(), 4, OTableLayout::Separate);
+
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes.pop();
+        std::fs::write(&path, bytes).unwrap();
+        let truncated = FmIndex::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matching.is_ok());
+        assert!(matches!(
+            other_genome,
+            Err(GeneSearchError::IndexMismatch(_))
+        ));
+        assert!(matches!(
+            other_spacing,
+            Err(GeneSearchError::IndexMismatch(_))
+        ));
+        assert!(matches!(truncated, Err(GeneSearchError::Parse(_))));
+    }
+
+    #[test]
+    fn test_zero_spacing_is_invalid() {
+        let reference = Reference::from_fasta(Cursor::new("ACGT\n"), "unnamed").unwrap();
+        let result = FmIndex::new(reference, 0, AmbiguityPolicy::NonMatching);
+
+        assert!(matches!(result, Err(GeneSearchError::InvalidArgument(_))));
+    }
+}
@@ -0,0 +1,316 @@
+use crate::approx_search::reverse_qualities;
+use crate::cigar::{Cigar, CigarOp};
+use crate::locate::{locate_exact, locate_interval, SuffixLookup};
+use crate::quality::QualityScoring;
+use crate::types::StrandAlignments;
+use crate::util::{reverse_complement, Strand};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+/// An alignment of a read, located to an offset in the concatenated reference.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hit {
+    pub strand: Strand,
+    /// 0-based offset of the first aligned reference base
+    pub position: usize,
+    pub edits: usize,
+    /// Quality-aware penalty of the alignment, or its edits when unscored.
+    /// Hits are ranked by it, and SAM reports its negation as the score.
+    pub penalty: usize,
+    pub cigar: Cigar,
+}
+
+/// Which of the loci a read aligns to are reported.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReportPolicy {
+    /// Only the best locus, the leftmost of those with the lowest penalty
+    BestOnly,
+    /// Every locus aligned with the lowest penalty
+    AllBest,
+    /// Every locus aligned with a penalty at most this much above the best
+    AllWithin(usize),
+}
+
+impl ReportPolicy {
+    /// Keeps the hits this policy reports, out of hits ordered by `collapse_hits`.
+    pub fn apply(self, hits: &mut Vec<Hit>) {
+        let best = match hits.first() {
+            Some(hit) => hit.penalty,
+            None => return,
+        };
+        match self {
+            ReportPolicy::BestOnly => hits.truncate(1),
+            ReportPolicy::AllBest => hits.retain(|hit| hit.penalty == best),
+            ReportPolicy::AllWithin(k) => hits.retain(|hit| hit.penalty - best <= k),
+        }
+    }
+}
+
+/// Orders CIGARs so that gaps come as early as possible, then substitutions.
+/// The first of several CIGARs of an alignment is then the left-aligned one.
+pub fn cmp_cigars(a: &Cigar, b: &Cigar) -> Ordering {
+    fn rank(op: CigarOp) -> u8 {
+        match op {
+            CigarOp::Deletion => 0,
+            CigarOp::Insertion => 1,
+            CigarOp::Mismatch => 2,
+            CigarOp::Match => 3,
+            CigarOp::SoftClip => 4,
+        }
+    }
+    a.ops().map(rank).cmp(b.ops().map(rank))
+}
+
+/// Where the first base of the read falls on the reference. A hit starting with
+/// insertions is the same occurrence as one aligning those bases to the reference
+/// bases before it, so they are counted as covering those.
+fn read_start(hit: &Hit) -> usize {
+    let leading_insertions = hit
+        .cigar
+        .ops()
+        .take_while(|&op| op == CigarOp::Insertion)
+        .count();
+    hit.position.saturating_sub(leading_insertions)
+}
+
+/// Keeps one hit per occurrence, taking hits on the same strand with the same
+/// position or the same `read_start` to be one: the one with the lowest penalty,
+/// then the fewest edits, then the first CIGAR by `cmp_cigars`. Sorted in that
+/// order, then by position and strand.
+pub fn collapse_hits(mut hits: Vec<Hit>) -> Vec<Hit> {
+    hits.sort_by(|a, b| {
+        (a.penalty, a.edits, a.position, a.strand)
+            .cmp(&(b.penalty, b.edits, b.position, b.strand))
+            .then_with(|| cmp_cigars(&a.cigar, &b.cigar))
+    });
+    let mut seen = HashSet::new();
+    hits.retain(|hit| {
+        // The keys of a dropped hit still belong to the occurrence it was merged into
+        let keys = [(hit.strand, hit.position), (hit.strand, read_start(hit))];
+        let new = keys.iter().all(|key| !seen.contains(key));
+        seen.extend(keys.iter().copied());
+        new
+    });
+    hits
+}
+
+/// Collapsed hits for the strand-tagged inclusive intervals returned by
+/// `bwt_search_strands`, for a read of `len` bases.
+pub fn hits_from_exact<S: SuffixLookup + ?Sized>(
+    intervals: &[(Strand, (usize, usize))],
+    len: usize,
+    suffix_array: &S,
+) -> Vec<Hit> {
+    let mut cigar = Cigar::new();
+    cigar.push_run(len, CigarOp::Match);
+    let hits = intervals
+        .iter()
+        .flat_map(|(strand, interval)| {
+            locate_exact(suffix_array, *interval, None)
+                .into_iter()
+                .map(|position| Hit {
+                    strand: *strand,
+                    position,
+                    edits: 0,
+                    penalty: 0,
+                    cigar: cigar.clone(),
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    collapse_hits(hits)
+}
+
+/// Collapsed hits for the results of `approx_search_strands` for a remapped
+/// query, scored with the scoring it was searched with, if any.
+pub fn hits_from_approx<S: SuffixLookup + ?Sized>(
+    results: &StrandAlignments,
+    suffix_array: &S,
+    query: &[u8],
+    scoring: Option<&QualityScoring>,
+) -> Vec<Hit> {
+    let reverse = reverse_complement(query);
+    let reverse_qualities = reverse_qualities(scoring.copied());
+    let reverse_scoring = scoring.map(|scoring| QualityScoring {
+        qualities: &reverse_qualities,
+        ..*scoring
+    });
+
+    let hits = results
+        .iter()
+        .flat_map(|(strand, left, right, cigar, edits)| {
+            let (query, scoring) = match strand {
+                Strand::Forward => (query, scoring),
+                Strand::Reverse => (&reverse[..], reverse_scoring.as_ref()),
+            };
+            let penalty = match scoring {
+                Some(scoring) => scoring.alignment_penalty(query, cigar),
+                None => *edits,
+            };
+            locate_interval(suffix_array, *left, *right, None)
+                .into_iter()
+                .map(move |position| Hit {
+                    strand: *strand,
+                    position,
+                    edits: *edits,
+                    penalty,
+                    cigar: cigar.clone(),
+                })
+        })
+        .collect();
+    collapse_hits(hits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        approx_search::{approx_search_strands, ApproxSearchParams, EditModel},
+        o_table::OTable,
+        quality::{phred_qualities, Penalties},
+        suffix_array_construction::construct_suffix_array_naive,
+        table_gen::generate_c_table,
+        util::{remap_query, remap_reference},
+    };
+
+    fn hit(position: usize, edits: usize, cigar: &str) -> Hit {
+        Hit {
+            strand: Strand::Forward,
+            position,
+            edits,
+            penalty: edits,
+            cigar: cigar.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_cmp_cigars() {
+        let cmp = |a: &str, b: &str| cmp_cigars(&a.parse().unwrap(), &b.parse().unwrap());
+        assert_eq!(cmp("1=1I1=", "2=1I"), Ordering::Less);
+        assert_eq!(cmp("1=1D2=", "2=1D1="), Ordering::Less);
+        assert_eq!(cmp("1=1X1=", "2=1X"), Ordering::Less);
+        assert_eq!(cmp("3=", "3="), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_att_collapses_to_one_hit_per_occurrence() {
+        let reference = remap_reference::<u8>("AGATAGATTCACA").unwrap();
+        let suffix_array = construct_suffix_array_naive(&reference);
+        let reverse_reference: Vec<u8> = reference.iter().rev().copied().collect();
+        let reverse_suffix_array = construct_suffix_array_naive(&reverse_reference);
+
+        let params = ApproxSearchParams {
+            reference: &reference,
+            query: &remap_query("ATT").unwrap(),
+            o_table: &OTable::new(&reference, &suffix_array, 10),
+            c_table: &generate_c_table(&reference),
+            rev_o_table: &OTable::new(&reverse_reference, &reverse_suffix_array, 10),
+            edits: 1,
+            edit_model: EditModel::Levenshtein,
+            wildcard_cost: 1,
+            scoring: None,
+        };
+        let results = approx_search_strands(params, false);
+        let hits = hits_from_approx(&results, &suffix_array, params.query, None);
+
+        // 2=1I and 1=1I1= land on the same position, and 1I2= at 7 is the exact
+        // match at 6 with its first base inserted
+        assert_eq!(hits, vec![hit(6, 0, "3="), hit(2, 1, "1=1I1=")]);
+    }
+
+    #[test]
+    fn test_collapse_merges_leading_insertions() {
+        // 1X1I6= has a position of its own, but is merged through 1I7=, whose read
+        // starts where the exact match does
+        let hits = collapse_hits(vec![
+            hit(34, 2, "1X1I6="),
+            hit(34, 1, "1I7="),
+            hit(33, 0, "8="),
+            hit(40, 1, "7=1I"),
+        ]);
+        assert_eq!(hits, vec![hit(33, 0, "8="), hit(40, 1, "7=1I")]);
+    }
+
+    #[test]
+    fn test_qualities_rank_hits() {
+        let reference = remap_reference::<u8>("AGGTCTTACCTC").unwrap();
+        let suffix_array = construct_suffix_array_naive(&reference);
+        let reverse_reference: Vec<u8> = reference.iter().rev().copied().collect();
+        let reverse_suffix_array = construct_suffix_array_naive(&reverse_reference);
+        let query = remap_query("ACGTC").unwrap();
+        let qualities = phred_qualities("II#II");
+        let scoring = QualityScoring {
+            qualities: &qualities,
+            penalties: Penalties::default(),
+            max_penalty: None,
+        };
+
+        let params = ApproxSearchParams {
+            reference: &reference,
+            query: &query,
+            o_table: &OTable::new(&reference, &suffix_array, 10),
+            c_table: &generate_c_table(&reference),
+            rev_o_table: &OTable::new(&reverse_reference, &reverse_suffix_array, 10),
+            edits: 1,
+            edit_model: EditModel::Hamming,
+            wildcard_cost: 1,
+            scoring: Some(scoring),
+        };
+        let results = approx_search_strands(params, false);
+        let positions = |hits: Vec<Hit>| {
+            hits.iter()
+                .map(|hit| (hit.position, hit.edits, hit.penalty))
+                .collect::<Vec<_>>()
+        };
+
+        // Unscored, the two mismatches tie and the leftmost comes first
+        let hits = hits_from_approx(&results, &suffix_array, &query, None);
+        assert_eq!(positions(hits), vec![(0, 1, 1), (7, 1, 1)]);
+        // The mismatch at the low quality base is the likelier alignment
+        let hits = hits_from_approx(&results, &suffix_array, &query, Some(&scoring));
+        assert_eq!(positions(hits), vec![(7, 1, 2), (0, 1, 6)]);
+
+        // A penalty limit prunes the other one during the search
+        let params = ApproxSearchParams {
+            scoring: Some(QualityScoring {
+                max_penalty: Some(4),
+                ..scoring
+            }),
+            ..params
+        };
+        let results = approx_search_strands(params, false);
+        let hits = hits_from_approx(&results, &suffix_array, &query, Some(&scoring));
+        assert_eq!(positions(hits), vec![(7, 1, 2)]);
+    }
+
+    #[test]
+    fn test_report_policies() {
+        let hits = collapse_hits(vec![
+            hit(9, 2, "1=2X"),
+            hit(4, 1, "2=1X"),
+            hit(4, 1, "1X2="),
+            hit(1, 1, "2=1I"),
+            hit(1, 2, "2X1="),
+            hit(7, 3, "3X"),
+        ]);
+        assert_eq!(
+            hits,
+            vec![
+                hit(1, 1, "2=1I"),
+                hit(4, 1, "1X2="),
+                hit(9, 2, "1=2X"),
+                hit(7, 3, "3X"),
+            ]
+        );
+
+        let reported = |policy: ReportPolicy| {
+            let mut hits = hits.clone();
+            policy.apply(&mut hits);
+            hits.iter().map(|hit| hit.position).collect::<Vec<_>>()
+        };
+        assert_eq!(reported(ReportPolicy::BestOnly), vec![1]);
+        assert_eq!(reported(ReportPolicy::AllBest), vec![1, 4]);
+        assert_eq!(reported(ReportPolicy::AllWithin(1)), vec![1, 4, 9]);
+        assert_eq!(reported(ReportPolicy::AllWithin(5)), vec![1, 4, 9, 7]);
+    }
+}
@@ -0,0 +1,357 @@
+use crate::error::{GeneSearchError, Result};
+use crate::o_table::OTableLayout;
+use crate::storage::CacheLine;
+use crate::ALPHABET;
+use std::convert::{TryFrom, TryInto};
+use std::io::{ErrorKind, Read, Write};
+
+/// The first bytes of every index file.
+pub const MAGIC: [u8; 8] = *b"GSINDEX\0";
+
+/// Sections of an index file start at multiples of this many bytes, the size
+/// of a cache line.
+pub const SECTION_ALIGN: usize = 64;
+
+/// Incremented whenever the layout of the index file changes.
+pub const FORMAT_VERSION: u32 = 6;
+
+/// Describes the contents of an index file. It is written before the tables, so
+/// a stale or foreign index can be rejected without reading them.
+///
+/// The file starts with `MAGIC`, followed by the header fields as little-endian
+/// integers padded to `SECTION_ALIGN` bytes, the alphabet as a section, and then
+/// the sections of the index. Every section is a `u64` element count padded to
+/// `SECTION_ALIGN` bytes, followed by the elements padded the same way, so that
+/// the elements of every section start on a cache line when the file is
+/// memory-mapped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexHeader {
+    pub version: u32,
+    pub alphabet: String,
+    /// Requested spacing of the O-tables
+    pub spacing: usize,
+    pub layout: OTableLayout,
+    /// Length of the remapped genome, including the sentinel
+    pub length: usize,
+    /// FNV-1a hash of the remapped genome
+    pub content_hash: u64,
+}
+
+impl IndexHeader {
+    pub fn new(genome: &[u8], spacing: usize, layout: OTableLayout) -> Self {
+        IndexHeader {
+            version: FORMAT_VERSION,
+            alphabet: ALPHABET.iter().collect(),
+            spacing,
+            layout,
+            length: genome.len(),
+            content_hash: content_hash(genome),
+        }
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&self.version.to_le_bytes())?;
+        writer.write_all(&self.layout.code().to_le_bytes())?;
+        write_u64(writer, self.spacing as u64)?;
+        write_u64(writer, self.length as u64)?;
+        write_u64(writer, self.content_hash)?;
+        writer.write_all(&[0; SECTION_ALIGN][..padding(HEADER_FIELDS_LEN)])?;
+        write_bytes(writer, self.alphabet.as_bytes())
+    }
+
+    /// Reads a header and checks that this build can read the rest of the file.
+    pub fn read<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut magic = [0; 8];
+        read_exact(reader, &mut magic)?;
+        if magic != MAGIC {
+            return Err(GeneSearchError::Parse(
+                "not a GeneSearch index file".to_string(),
+            ));
+        }
+
+        let mut version_and_layout = [0; 8];
+        read_exact(reader, &mut version_and_layout)?;
+        let [v0, v1, v2, v3, l0, l1, l2, l3] = version_and_layout;
+        let version = u32::from_le_bytes([v0, v1, v2, v3]);
+        if version != FORMAT_VERSION {
+            return Err(GeneSearchError::FormatVersion {
+                found: version,
+                expected: FORMAT_VERSION,
+            });
+        }
+
+        let layout = u32::from_le_bytes([l0, l1, l2, l3]);
+        let layout = OTableLayout::from_code(layout)
+            .ok_or_else(|| GeneSearchError::Parse(format!("unknown O-table layout {}", layout)))?;
+        let spacing = read_usize(reader)?;
+        let length = read_usize(reader)?;
+        let content_hash = read_u64(reader)?;
+        read_exact(
+            reader,
+            &mut [0; SECTION_ALIGN][..padding(HEADER_FIELDS_LEN)],
+        )?;
+        let alphabet = String::from_utf8(read_bytes(reader)?)
+            .map_err(|_| GeneSearchError::Parse("index alphabet is not UTF-8".to_string()))?;
+
+        let expected: String = ALPHABET.iter().collect();
+        if alphabet != expected {
+            return Err(GeneSearchError::IndexMismatch(format!(
+                "index was built for alphabet {:?}, but this build uses {:?}",
+                alphabet, expected
+            )));
+        }
+
+        Ok(IndexHeader {
+            version,
+            alphabet,
+            spacing,
+            layout,
+            length,
+            content_hash,
+        })
+    }
+
+    /// Checks that the index was built from `genome` with the given O-table settings.
+    pub fn check_matches(&self, genome: &[u8], spacing: usize, layout: OTableLayout) -> Result<()> {
+        if self.spacing != spacing {
+            return Err(GeneSearchError::IndexMismatch(format!(
+                "index has O-table spacing {}, but {} was requested",
+                self.spacing, spacing
+            )));
+        }
+        if self.layout != layout {
+            return Err(GeneSearchError::IndexMismatch(format!(
+                "index has the {:?} O-table layout, but {:?} was requested",
+                self.layout, layout
+            )));
+        }
+        if self.length != genome.len() || self.content_hash != content_hash(genome) {
+            return Err(GeneSearchError::IndexMismatch(
+                "index was built from a different genome".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// 64-bit FNV-1a hash of a remapped genome.
+pub fn content_hash(genome: &[u8]) -> u64 {
+    genome.iter().fold(0xcbf29ce484222325, |hash, &c| {
+        (hash ^ c as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn write_u64<W: Write>(writer: &mut W, value: u64) -> Result<()> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+/// Like `Read::read_exact`, but reports running out of input as a truncated index.
+fn read_exact<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<()> {
+    reader.read_exact(buffer).map_err(|e| match e.kind() {
+        ErrorKind::UnexpectedEof => truncated(),
+        _ => GeneSearchError::Io(e),
+    })
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut bytes = [0; 8];
+    read_exact(reader, &mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_usize<R: Read>(reader: &mut R) -> Result<usize> {
+    let value = read_u64(reader)?;
+    usize::try_from(value)
+        .map_err(|_| GeneSearchError::Parse(format!("{} does not fit in usize", value)))
+}
+
+/// Bytes taken by the magic number and the header fields.
+const HEADER_FIELDS_LEN: usize = 40;
+
+fn padding(len: usize) -> usize {
+    (SECTION_ALIGN - len % SECTION_ALIGN) % SECTION_ALIGN
+}
+
+/// Writes the element count that starts a section.
+fn write_count<W: Write>(writer: &mut W, count: usize) -> Result<()> {
+    write_u64(writer, count as u64)?;
+    writer.write_all(&[0; SECTION_ALIGN][..padding(8)])?;
+    Ok(())
+}
+
+/// Pads the elements of a section that took `len` bytes.
+fn write_padding<W: Write>(writer: &mut W, len: usize) -> Result<()> {
+    writer.write_all(&[0; SECTION_ALIGN][..padding(len)])?;
+    Ok(())
+}
+
+/// Writes a byte section.
+pub(crate) fn write_bytes<W: Write>(writer: &mut W, bytes: &[u8]) -> Result<()> {
+    write_count(writer, bytes.len())?;
+    writer.write_all(bytes)?;
+    write_padding(writer, bytes.len())
+}
+
+/// Reads a byte section.
+fn read_bytes<R: Read>(reader: &mut R) -> Result<Vec<u8>> {
+    let len = read_usize(reader)?;
+    read_exact(reader, &mut [0; SECTION_ALIGN][..padding(8)])?;
+    let mut bytes = Vec::new();
+    reader.by_ref().take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len {
+        return Err(truncated());
+    }
+    read_exact(reader, &mut [0; SECTION_ALIGN][..padding(len)])?;
+    Ok(bytes)
+}
+
+/// Writes a section of integers, each stored as a little-endian `u64`.
+pub(crate) fn write_usizes<W: Write>(writer: &mut W, values: &[usize]) -> Result<()> {
+    write_count(writer, values.len())?;
+    for &value in values {
+        write_u64(writer, value as u64)?;
+    }
+    write_padding(writer, values.len() * 8)
+}
+
+/// Writes a section of 64-bit words.
+pub(crate) fn write_words<W: Write>(writer: &mut W, words: &[u64]) -> Result<()> {
+    write_count(writer, words.len())?;
+    for &word in words {
+        write_u64(writer, word)?;
+    }
+    write_padding(writer, words.len() * 8)
+}
+
+/// Writes a section of cache lines, each stored as eight little-endian words.
+pub(crate) fn write_lines<W: Write>(writer: &mut W, lines: &[CacheLine]) -> Result<()> {
+    write_count(writer, lines.len())?;
+    for &word in CacheLine::words(lines) {
+        write_u64(writer, word)?;
+    }
+    Ok(())
+}
+
+/// Finds the sections of an index file in memory, so they can be used in place
+/// instead of being copied.
+pub(crate) struct SectionReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> SectionReader<'a> {
+    /// Reads the sections starting at byte `position`, usually the end of the header.
+    pub fn new(bytes: &'a [u8], position: usize) -> Self {
+        SectionReader { bytes, position }
+    }
+
+    /// Skips past the next section, returning the byte offset of its first element
+    /// and the number of elements.
+    pub fn next_section(&mut self, element_size: usize) -> Result<(usize, usize)> {
+        let count = self
+            .bytes
+            .get(self.position..self.position + 8)
+            .ok_or_else(truncated)?;
+        let count = u64::from_le_bytes(count.try_into().unwrap());
+        let len = usize::try_from(count)
+            .map_err(|_| GeneSearchError::Parse(format!("{} does not fit in usize", count)))?;
+
+        let offset = self.position + SECTION_ALIGN;
+        self.position = len
+            .checked_mul(element_size)
+            .and_then(|size| offset.checked_add(size + padding(size)))
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or_else(truncated)?;
+        Ok((offset, len))
+    }
+
+    pub fn next_bytes(&mut self) -> Result<&'a [u8]> {
+        let (offset, len) = self.next_section(1)?;
+        Ok(&self.bytes[offset..offset + len])
+    }
+}
+
+fn truncated() -> GeneSearchError {
+    GeneSearchError::Parse("index file is truncated".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_header_round_trip() {
+        let header = IndexHeader::new(&[1, 2, 3, 4, 0], 7, OTableLayout::Interleaved);
+        let mut bytes = Vec::new();
+        header.write(&mut bytes).unwrap();
+
+        assert_eq!(&bytes[..8], &MAGIC);
+        assert_eq!(bytes.len() % 8, 0);
+        let read = IndexHeader::read(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(read, header);
+        assert_eq!(read.alphabet, "$ACGT");
+    }
+
+    #[test]
+    fn test_header_rejects_bad_magic_and_version() {
+        let mut bytes = Vec::new();
+        IndexHeader::new(&[1, 0], 1, OTableLayout::Separate)
+            .write(&mut bytes)
+            .unwrap();
+
+        let mut wrong_magic = bytes.clone();
+        wrong_magic[0] = b'X';
+        let result = IndexHeader::read(&mut Cursor::new(wrong_magic));
+        assert!(matches!(result, Err(GeneSearchError::Parse(_))));
+
+        let mut wrong_version = bytes;
+        wrong_version[8] = 99;
+        let result = IndexHeader::read(&mut Cursor::new(wrong_version));
+        assert!(matches!(
+            result,
+            Err(GeneSearchError::FormatVersion { found: 99, .. })
+        ));
+    }
+
+    #[test]
+    fn test_check_matches() {
+        let header = IndexHeader::new(&[1, 2, 3, 0], 4, OTableLayout::Separate);
+
+        assert!(header
+            .check_matches(&[1, 2, 3, 0], 4, OTableLayout::Separate)
+            .is_ok());
+        assert!(matches!(
+            header.check_matches(&[1, 2, 4, 0], 4, OTableLayout::Separate),
+            Err(GeneSearchError::IndexMismatch(_))
+        ));
+        assert!(matches!(
+            header.check_matches(&[1, 2, 3, 0], 5, OTableLayout::Separate),
+            Err(GeneSearchError::IndexMismatch(_))
+        ));
+        assert!(matches!(
+            header.check_matches(&[1, 2, 3, 0], 4, OTableLayout::Interleaved),
+            Err(GeneSearchError::IndexMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_sections_round_trip() {
+        let mut bytes = Vec::new();
+        write_bytes(&mut bytes, b"ACG").unwrap();
+        write_usizes(&mut bytes, &[3, 1, usize::MAX >> 1]).unwrap();
+        assert_eq!(bytes.len(), 4 * SECTION_ALIGN);
+
+        let mut reader = SectionReader::new(&bytes, 0);
+        assert_eq!(reader.next_bytes().unwrap(), b"ACG");
+        assert_eq!(reader.next_section(8).unwrap(), (3 * SECTION_ALIGN, 3));
+        assert_eq!(&bytes[192..200], &3u64.to_le_bytes());
+
+        let mut section = Vec::new();
+        write_usizes(&mut section, &[1, 2]).unwrap();
+        let result = SectionReader::new(&section[..section.len() - 1], 0).next_section(8);
+        assert!(matches!(result, Err(GeneSearchError::Parse(_))));
+    }
+}
@@ -0,0 +1,115 @@
+use crate::error::{with_path, GeneSearchError, Result};
+use flate2::read::MultiGzDecoder;
+use std::fs::File;
+use std::io::{stdin, Cursor, Read};
+use std::path::Path;
+
+/// The first two bytes of every gzip member.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// An opened input file, decompressed if needed.
+pub type Input = Box<dyn Read + Send>;
+
+/// Whether a path means standard input.
+pub fn is_stdin(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+/// Opens a file for reading, decompressing it if it starts like a gzip file.
+/// BGZF files are gzip files of many members, so they are read the same way.
+pub fn open_input(path: &Path) -> Result<Input> {
+    let opened = File::open(path)
+        .map_err(GeneSearchError::from)
+        .and_then(decompressed);
+    with_path(opened, path)
+}
+
+/// Like `open_input`, but `-` reads standard input.
+pub fn open_input_or_stdin(path: &Path) -> Result<Input> {
+    if is_stdin(path) {
+        decompressed(stdin())
+    } else {
+        open_input(path)
+    }
+}
+
+/// Decompresses a reader if it starts like a gzip file, and passes it through
+/// otherwise. Only the first two bytes are read to decide, so pipes work too.
+pub fn decompressed<R: Read + Send + 'static>(mut reader: R) -> Result<Input> {
+    let mut magic = [0; 2];
+    let mut len = 0;
+    while len < magic.len() {
+        match reader.read(&mut magic[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+
+    let reader = Cursor::new(magic).take(len as u64).chain(reader);
+    if magic[..len] == GZIP_MAGIC {
+        Ok(Box::new(MultiGzDecoder::new(reader)))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// The file name without its directory and extension, ignoring a compression
+/// extension, so `chr1.fa.gz` gives `chr1`.
+pub fn input_stem(path: &Path) -> String {
+    let path = match path.extension() {
+        Some(extension) if extension == "gz" || extension == "bgz" => path.with_extension(""),
+        _ => path.to_path_buf(),
+    };
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn read_all(mut input: Input) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_decompressed() {
+        let plain = b">chr1\nACGT\n".to_vec();
+        assert_eq!(
+            read_all(decompressed(Cursor::new(plain.clone())).unwrap()),
+            plain
+        );
+        assert_eq!(
+            read_all(decompressed(Cursor::new(gzip(&plain))).unwrap()),
+            plain
+        );
+        assert_eq!(
+            read_all(decompressed(Cursor::new(vec![b'>'])).unwrap()),
+            b">"
+        );
+
+        // Concatenated members, as in BGZF files
+        let mut members = gzip(b">chr1\n");
+        members.extend(gzip(b"ACGT\n"));
+        assert_eq!(read_all(decompressed(Cursor::new(members)).unwrap()), plain);
+    }
+
+    #[test]
+    fn test_input_stem() {
+        assert_eq!(input_stem(Path::new("genomes/chr1.fa.gz")), "chr1");
+        assert_eq!(input_stem(Path::new("genomes/chr1.fa")), "chr1");
+        assert_eq!(input_stem(Path::new("reads.fq.bgz")), "reads");
+    }
+}
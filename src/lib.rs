@@ -0,0 +1,42 @@
+//! Matches reads to genomes using suffix arrays and FM-index search.
+//!
+//! `FmIndex` owns a reference together with all the tables needed to search it,
+//! and is the entry point for most uses. The modules expose the underlying
+//! algorithms for callers that want to build or combine the tables themselves.
+
+pub mod alignment;
+pub mod approx_search;
+pub mod batch;
+pub mod bidirectional;
+pub mod cigar;
+pub mod error;
+pub mod exact_search;
+pub mod fm_index;
+pub mod hits;
+pub mod index_file;
+pub mod input;
+pub mod locate;
+pub mod o_table;
+pub mod packed_bwt;
+pub mod quality;
+pub mod rank;
+pub mod reference;
+pub mod sais;
+pub mod sam;
+pub mod sampled_suffix_array;
+pub mod search_scheme;
+pub mod skew;
+pub mod storage;
+pub mod suffix_array_construction;
+pub mod table_gen;
+pub mod types;
+pub mod util;
+pub mod wavelet_tree;
+
+pub use error::{GeneSearchError, Result};
+pub use fm_index::FmIndex;
+pub use reference::Reference;
+pub use util::{AmbiguityPolicy, Strand};
+
+/// The symbols of a remapped reference, in the order of their codes.
+pub const ALPHABET: [char; 5] = ['$', 'A', 'C', 'G', 'T'];
@@ -0,0 +1,134 @@
+use crate::types::Alignments;
+
+/// Anything that can answer "which text position does the suffix of rank i start at".
+pub trait SuffixLookup {
+    fn suffix(&self, i: usize) -> usize;
+}
+
+impl SuffixLookup for [usize] {
+    fn suffix(&self, i: usize) -> usize {
+        self[i]
+    }
+}
+
+impl SuffixLookup for Vec<usize> {
+    fn suffix(&self, i: usize) -> usize {
+        self[i]
+    }
+}
+
+/// Resolves the half-open suffix array interval `[left, right)` into 0-based
+/// reference offsets, sorted and deduplicated. At most `max_hits` offsets are
+/// returned when a cap is given.
+pub fn locate_interval<S: SuffixLookup + ?Sized>(
+    suffix_array: &S,
+    left: usize,
+    right: usize,
+    max_hits: Option<usize>,
+) -> Vec<usize> {
+    if left >= right {
+        return Vec::new();
+    }
+
+    let mut positions: Vec<usize> = (left..right).map(|i| suffix_array.suffix(i)).collect();
+    positions.sort_unstable();
+    positions.dedup();
+
+    if let Some(max_hits) = max_hits {
+        positions.truncate(max_hits);
+    }
+
+    positions
+}
+
+/// Resolves the inclusive interval returned by `bwt_search` into reference offsets.
+/// An empty interval (start > end) yields no offsets.
+pub fn locate_exact<S: SuffixLookup + ?Sized>(
+    suffix_array: &S,
+    interval: (usize, usize),
+    max_hits: Option<usize>,
+) -> Vec<usize> {
+    let (start, end) = interval;
+    locate_interval(suffix_array, start, end + 1, max_hits)
+}
+
+/// Resolves all the half-open intervals returned by `approx_search` into the
+/// union of their reference offsets.
+pub fn locate_approx<S: SuffixLookup + ?Sized>(
+    suffix_array: &S,
+    results: &Alignments,
+    max_hits: Option<usize>,
+) -> Vec<usize> {
+    let mut positions: Vec<usize> = results
+        .iter()
+        .flat_map(|(left, right, _, _)| locate_interval(suffix_array, *left, *right, None))
+        .collect();
+    positions.sort_unstable();
+    positions.dedup();
+
+    if let Some(max_hits) = max_hits {
+        positions.truncate(max_hits);
+    }
+
+    positions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        approx_search::{approx_search, ApproxSearchParams, EditModel},
+        exact_search::bwt_search,
+        o_table::OTable,
+        suffix_array_construction::construct_suffix_array_naive,
+        table_gen::generate_c_table,
+        util::{remap_query, remap_reference},
+    };
+
+    #[test]
+    fn test_locate_exact_agaga() {
+        let reference = remap_reference("AGAGA").unwrap();
+        let suffix_array = construct_suffix_array_naive(&reference);
+        let o_table = OTable::new(&reference, &suffix_array, 10);
+        let c_table = generate_c_table(&reference);
+        let interval = bwt_search(&remap_query("AGA").unwrap(), &o_table, &c_table);
+
+        assert_eq!(locate_exact(&suffix_array, interval, None), vec![0, 2]);
+        assert_eq!(locate_exact(&suffix_array, interval, Some(1)), vec![0]);
+    }
+
+    #[test]
+    fn test_locate_exact_no_match() {
+        let reference = remap_reference("AGAGA").unwrap();
+        let suffix_array = construct_suffix_array_naive(&reference);
+        let o_table = OTable::new(&reference, &suffix_array, 10);
+        let c_table = generate_c_table(&reference);
+        let interval = bwt_search(&remap_query("ACA").unwrap(), &o_table, &c_table);
+
+        assert!(locate_exact(&suffix_array, interval, None).is_empty());
+    }
+
+    #[test]
+    fn test_locate_approx_att() {
+        let reference = remap_reference::<u8>("AGATAGATTCACA").unwrap();
+        let suffix_array = construct_suffix_array_naive(&reference);
+        let reverse_reference: Vec<u8> = reference.iter().rev().copied().collect();
+        let reverse_suffix_array = construct_suffix_array_naive(&reverse_reference);
+
+        let params = ApproxSearchParams {
+            reference: &reference,
+            query: &remap_query("ATT").unwrap(),
+            o_table: &OTable::new(&reference, &suffix_array, 10),
+            c_table: &generate_c_table(&reference),
+            rev_o_table: &OTable::new(&reverse_reference, &reverse_suffix_array, 10),
+            edits: 1,
+            edit_model: EditModel::Levenshtein,
+            wildcard_cost: 1,
+            scoring: None,
+        };
+        let results = approx_search(params);
+
+        assert_eq!(locate_approx(&suffix_array, &results, None), vec![2, 6, 7]);
+        assert_eq!(locate_approx(&suffix_array, &results, Some(2)), vec![2, 6]);
+    }
+}
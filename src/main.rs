@@ -1,280 +1,747 @@
-mod approx_search;
-mod exact_search;
-mod o_table;
-mod sais;
-mod skew;
-mod suffix_array_construction;
-mod table_gen;
-mod types;
-mod util;
-
-use approx_search::{approx_search, ApproxSearchParams};
+mod cli;
+
 use chrono::Local;
-use exact_search::bwt_search;
-use o_table::OTable;
-use sais::suffix_array_induced_sort;
-use skew::skew;
-use std::fs::{create_dir, File};
-use std::io::Write;
-use std::path::Path;
+use clap::Parser;
+use cli::{
+    Algorithm, BenchArgs, Cli, Command, Distance, GenomeArgs, Layout, RankStructure, Report,
+    Scheme, SearchArgs,
+};
+use gene_search::alignment::verify_hit;
+use gene_search::approx_search::{
+    approx_search, approx_search_strands, approx_search_with_stats, ApproxSearchParams, EditModel,
+    SearchStats,
+};
+use gene_search::batch::map_in_order;
+use gene_search::error::with_path;
+use gene_search::exact_search::{bwt_search, bwt_search_strands, exact_binary_search};
+use gene_search::hits::{hits_from_approx, hits_from_exact, Hit, ReportPolicy};
+use gene_search::input::input_stem;
+use gene_search::locate::{locate_approx, locate_exact, locate_interval};
+use gene_search::o_table::{OTable, OTableLayout};
+use gene_search::quality::{phred_qualities, Penalties, QualityScoring};
+use gene_search::rank::Rank;
+use gene_search::reference::Reference;
+use gene_search::sais::suffix_array_induced_sort;
+use gene_search::sam::{records_from_approx, records_from_exact, SamWriter};
+use gene_search::sampled_suffix_array::{SampledSuffixArray, SamplingStrategy};
+use gene_search::search_scheme::{scheme_search_strands, scheme_search_with_stats, SearchScheme};
+use gene_search::skew::skew;
+use gene_search::suffix_array_construction::construct_suffix_array_naive;
+use gene_search::table_gen::generate_c_table;
+use gene_search::types::{Alignments as ApproxAlignments, StrandAlignments};
+use gene_search::util::*;
+use gene_search::wavelet_tree::WaveletTree;
+use gene_search::{FmIndex, GeneSearchError, Result, ALPHABET};
+use std::fs::{create_dir, create_dir_all, File};
+use std::io::{stdout, BufWriter, ErrorKind, Write};
+use std::mem::size_of_val;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
-use suffix_array_construction::construct_suffix_array_naive;
-use table_gen::generate_c_table;
-use types::*;
-use util::*;
-
-use crate::exact_search::exact_binary_search;
 
-const ALPHABET: [char; 5] = ['$', 'A', 'C', 'G', 'T'];
 const HG38_1000: &str = "hg38-1000";
 
+/// Number of reads searched before their results are written.
+const BATCH_SIZE: usize = 10_000;
+
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
-
-    if args.len() > 1 {
-        match args[1].as_str() {
-            "sais" => time_sais(args),
-            "skew" => time_skew(args),
-            "naive-sa" => time_naive_sa(args),
-            "otable" => time_o_table(args),
-            "approx" => time_approx(args),
-            "exact-bwt" => time_exact_bwt(args),
-            "exact-binary" => time_exact_binary(args),
-            _ => println!("Wut"),
-        }
+    let cli = Cli::parse();
+
+    if let Err(e) = run(cli.command) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(command: Command) -> Result<()> {
+    match command {
+        Command::Index(args) => index(&args),
+        Command::Map(args) => map(&args),
+        Command::Count(args) => count(&args),
+        Command::Locate(args) => locate(&args),
+        Command::Stats(args) => stats(&args),
+        Command::Bench(args) => match args.algorithm {
+            Algorithm::Sais => time_sais(&args),
+            Algorithm::Skew => time_skew(&args),
+            Algorithm::NaiveSa => time_naive_sa(&args),
+            Algorithm::Otable => time_o_table(&args),
+            Algorithm::SampledSa => time_sampled_sa(&args),
+            Algorithm::Approx => time_approx(&args),
+            Algorithm::ExactBwt => time_exact_bwt(&args),
+            Algorithm::ExactBinary => time_exact_binary(&args),
+        },
+    }
+}
+
+/// The name the index of a genome is cached under.
+fn cache_name(genome: &Path, args: &GenomeArgs) -> String {
+    let mut name = input_stem(genome);
+    if args.randomize_n {
+        name.push_str("_random");
+    }
+    if args.layout == Layout::Interleaved {
+        name.push_str("_interleaved");
+    }
+    name
+}
+
+fn load_genome(args: &GenomeArgs) -> Result<(Reference, Vec<u8>)> {
+    let path = args.genome.as_ref().ok_or_else(|| {
+        GeneSearchError::InvalidArgument("this command needs a --genome".to_string())
+    })?;
+    let reference = Reference::from_path(path)?;
+    let genome = remap_reference_with_policy(reference.text()?, ambiguity_policy(args))?.sequence;
+    Ok((reference, genome))
+}
+
+fn o_table_layout(args: &GenomeArgs) -> OTableLayout {
+    match args.layout {
+        Layout::Separate => OTableLayout::Separate,
+        Layout::Interleaved => OTableLayout::Interleaved,
+    }
+}
+
+fn ambiguity_policy(args: &GenomeArgs) -> AmbiguityPolicy {
+    if args.randomize_n {
+        AmbiguityPolicy::Randomize
     } else {
-        println!("Nothing specified, quitting")
+        AmbiguityPolicy::NonMatching
     }
 }
 
-pub fn time_sais(args: Vec<String>) {
-    let genome_file_name = &args[2];
-    let iterations: u128 = args[3].parse().unwrap();
-    let output = args
-        .iter()
-        .find(|s| *s == &"--no-output".to_owned())
-        .is_none();
-    let genome = read_and_remap_genome(genome_file_name);
+/// Where the index of a genome is stored: the `--index` file, or else the cache.
+fn index_path(genome: &Path, args: &GenomeArgs) -> PathBuf {
+    if let Some(path) = &args.index {
+        return path.clone();
+    }
+    let mut path = Path::new("resources/index/").join(cache_name(genome, args));
+    path.set_extension(args.spacing.to_string());
+    path
+}
+
+/// Memory-maps the index of a genome, building and saving it if it is missing.
+/// An index that was built from other contents or settings, or that cannot be
+/// read, is rebuilt. Without a genome, the `--index` file is used as is.
+fn load_index(args: &GenomeArgs) -> Result<FmIndex> {
+    let genome_path = match (&args.genome, &args.index) {
+        (Some(genome_path), _) => genome_path,
+        (None, Some(path)) => return FmIndex::open(path),
+        (None, None) => {
+            return Err(GeneSearchError::InvalidArgument(
+                "either --genome or --index is required".to_string(),
+            ))
+        }
+    };
+
+    let path = index_path(genome_path, args);
+    let (reference, genome) = load_genome(args)?;
+    match FmIndex::open_matching(&path, &genome, args.spacing, o_table_layout(args)) {
+        Ok(index) => return Ok(index),
+        Err(GeneSearchError::Io(e)) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => eprintln!("Rebuilding the index: {}", e),
+    }
+
+    let index = FmIndex::with_layout(
+        reference,
+        args.spacing,
+        o_table_layout(args),
+        ambiguity_policy(args),
+    )?;
+    if let Some(dir) = path.parent() {
+        with_path(create_dir_all(dir).map_err(GeneSearchError::from), dir)?;
+    }
+    index.save(&path)?;
+    Ok(index)
+}
+
+/// Opens the `--output` file, or stdout if none was given.
+fn open_output(args: &SearchArgs) -> Result<Box<dyn Write>> {
+    match &args.output {
+        Some(path) => {
+            let file = with_path(File::create(path).map_err(GeneSearchError::from), path)?;
+            Ok(Box::new(BufWriter::new(file)))
+        }
+        None => Ok(Box::new(BufWriter::new(stdout()))),
+    }
+}
+
+/// The result of searching for a read, as SA intervals.
+enum Alignments {
+    Exact(Vec<(Strand, (usize, usize))>),
+    Approx(StrandAlignments),
+}
+
+fn edit_model(distance: Distance) -> EditModel {
+    match distance {
+        Distance::Edit => EditModel::Levenshtein,
+        Distance::Hamming => EditModel::Hamming,
+    }
+}
+
+/// The search scheme to search with for `edits` edits, or `None` to backtrack.
+fn search_scheme(scheme: Scheme, edits: usize) -> Result<Option<SearchScheme>> {
+    match scheme {
+        Scheme::Backtracking => Ok(None),
+        Scheme::Pigeonhole => Ok(Some(SearchScheme::pigeonhole(edits))),
+        Scheme::Kucherov => match SearchScheme::kucherov(edits) {
+            Some(scheme) => Ok(Some(scheme)),
+            None => Err(GeneSearchError::InvalidArgument(format!(
+                "there is no Kucherov scheme for {} edits",
+                edits
+            ))),
+        },
+    }
+}
+
+/// The quality-aware scoring of a read with Phred `qualities`, with `--qualities`.
+fn read_scoring<'a>(args: &SearchArgs, qualities: &'a [u8]) -> Option<QualityScoring<'a>> {
+    match args.qualities {
+        true => Some(QualityScoring {
+            qualities,
+            penalties: Penalties::default(),
+            max_penalty: args.max_penalty,
+        }),
+        false => None,
+    }
+}
+
+/// Searches for a remapped read with exact search, or approximate search if edits
+/// are allowed.
+fn search_read(
+    index: &FmIndex,
+    args: &SearchArgs,
+    scheme: Option<&SearchScheme>,
+    query: &[u8],
+    scoring: Option<QualityScoring>,
+) -> Alignments {
+    if args.edits == 0 {
+        return Alignments::Exact(bwt_search_strands(
+            query,
+            index.o_table(),
+            index.c_table(),
+            args.both_strands,
+        ));
+    }
+    let params = ApproxSearchParams {
+        edit_model: edit_model(args.distance),
+        scoring,
+        ..index.approx_params(query, args.edits, args.n_cost)
+    };
+    Alignments::Approx(match scheme {
+        Some(scheme) => scheme_search_strands(params, scheme, args.both_strands),
+        None => approx_search_strands(params, args.both_strands),
+    })
+}
+
+fn report_policy(args: &SearchArgs) -> ReportPolicy {
+    match args.report {
+        Report::Best => ReportPolicy::BestOnly,
+        Report::AllBest => ReportPolicy::AllBest,
+        Report::All => ReportPolicy::AllWithin(args.within.unwrap_or(usize::MAX)),
+    }
+}
+
+/// Locates the alignments of a remapped read, with one hit per position, and
+/// keeps those `policy` reports. Sorted by penalty, then edits, position and strand.
+fn located_hits(
+    index: &FmIndex,
+    query: &[u8],
+    alignments: &Alignments,
+    scoring: Option<&QualityScoring>,
+    policy: ReportPolicy,
+) -> Vec<Hit> {
+    let mut hits = match alignments {
+        Alignments::Exact(intervals) => {
+            hits_from_exact(intervals, query.len(), index.suffix_array())
+        }
+        Alignments::Approx(results) => {
+            hits_from_approx(results, index.suffix_array(), query, scoring)
+        }
+    };
+    policy.apply(&mut hits);
+    hits
+}
+
+/// Checks every hit of a read against the genome, when `--verify` is given.
+fn verify_hits(
+    index: &FmIndex,
+    args: &SearchArgs,
+    read: &ReadRecord,
+    query: &[u8],
+    hits: &[Hit],
+) -> Result<()> {
+    if !args.verify {
+        return Ok(());
+    }
+    for hit in hits {
+        verify_hit(
+            index.genome(),
+            query,
+            hit,
+            edit_model(args.distance),
+            args.n_cost,
+        )
+        .map_err(|e| GeneSearchError::Verification(format!("read {}: {}", read.name, e)))?;
+    }
+    Ok(())
+}
+
+fn strand_symbol(strand: Strand) -> char {
+    match strand {
+        Strand::Forward => '+',
+        Strand::Reverse => '-',
+    }
+}
+
+pub fn index(args: &GenomeArgs) -> Result<()> {
+    let time = Instant::now();
+    let index = load_index(args)?;
+    println!(
+        "Indexed {} bases in {} records in {} ms",
+        index.genome().len() - 1,
+        index.reference().records.len(),
+        time.elapsed().as_millis()
+    );
+    Ok(())
+}
+
+pub fn map(args: &SearchArgs) -> Result<()> {
+    let index = load_index(&args.genome)?;
+    let reads = ReadSource::from_path(&args.reads)?;
+    let scheme = search_scheme(args.scheme, args.edits)?;
+    let policy = report_policy(args);
+
+    let mut writer = SamWriter::new(open_output(args)?);
+    writer.write_header(index.reference())?;
+    for batch in reads.batches(BATCH_SIZE) {
+        let batch = batch?;
+        let batch_records = index.map_reads(&batch, args.threads, |index, read| -> Result<_> {
+            let qualities = phred_qualities(&read.quality);
+            let scoring = read_scoring(args, &qualities);
+            let query = remap_query(&read.sequence)?;
+            let alignments = search_read(index, args, scheme.as_ref(), &query, scoring);
+            if args.verify {
+                verify_hits(
+                    index,
+                    args,
+                    read,
+                    &query,
+                    &located_hits(index, &query, &alignments, scoring.as_ref(), policy),
+                )?;
+            }
+            let mut records = match alignments {
+                Alignments::Exact(intervals) => records_from_exact(
+                    read,
+                    &intervals,
+                    index.suffix_array(),
+                    index.reference(),
+                    policy,
+                ),
+                Alignments::Approx(results) => records_from_approx(
+                    read,
+                    &query,
+                    &results,
+                    index.suffix_array(),
+                    index.reference(),
+                    scoring.as_ref(),
+                    policy,
+                ),
+            };
+            if let Some(max_hits) = args.max_hits {
+                records.truncate(max_hits);
+            }
+            Ok(records)
+        });
+        for records in batch_records {
+            writer.write_records(&records?)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn count(args: &SearchArgs) -> Result<()> {
+    let index = load_index(&args.genome)?;
+    let reads = ReadSource::from_path(&args.reads)?;
+    let scheme = search_scheme(args.scheme, args.edits)?;
+    let policy = report_policy(args);
+
+    let mut output = open_output(args)?;
+    for batch in reads.batches(BATCH_SIZE) {
+        let batch = batch?;
+        let counts = index.map_reads(&batch, args.threads, |index, read| -> Result<_> {
+            let qualities = phred_qualities(&read.quality);
+            let scoring = read_scoring(args, &qualities);
+            let query = remap_query(&read.sequence)?;
+            let alignments = search_read(index, args, scheme.as_ref(), &query, scoring);
+            let hits = located_hits(index, &query, &alignments, scoring.as_ref(), policy);
+            verify_hits(index, args, read, &query, &hits)?;
+            Ok(hits.len())
+        });
+        for (read, count) in batch.iter().zip(counts) {
+            writeln!(output, "{}\t{}", read.name, count?)?;
+        }
+    }
+    output.flush()?;
+    Ok(())
+}
+
+pub fn locate(args: &SearchArgs) -> Result<()> {
+    let index = load_index(&args.genome)?;
+    let reads = ReadSource::from_path(&args.reads)?;
+    let scheme = search_scheme(args.scheme, args.edits)?;
+    let policy = report_policy(args);
+
+    let mut output = open_output(args)?;
+    for batch in reads.batches(BATCH_SIZE) {
+        let batch = batch?;
+        let batch_hits = index.map_reads(&batch, args.threads, |index, read| -> Result<_> {
+            let qualities = phred_qualities(&read.quality);
+            let scoring = read_scoring(args, &qualities);
+            let query = remap_query(&read.sequence)?;
+            let alignments = search_read(index, args, scheme.as_ref(), &query, scoring);
+            let mut hits = located_hits(index, &query, &alignments, scoring.as_ref(), policy);
+            verify_hits(index, args, read, &query, &hits)?;
+            if let Some(max_hits) = args.max_hits {
+                hits.truncate(max_hits);
+            }
+            Ok(hits)
+        });
+
+        for (read, hits) in batch.iter().zip(batch_hits) {
+            for hit in hits? {
+                if let Some((record, offset)) = index.reference().resolve(hit.position) {
+                    writeln!(
+                        output,
+                        "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                        read.name,
+                        record,
+                        offset,
+                        strand_symbol(hit.strand),
+                        hit.edits,
+                        hit.penalty,
+                        hit.cigar.to_sam()
+                    )?;
+                }
+            }
+        }
+    }
+    output.flush()?;
+    Ok(())
+}
+
+pub fn stats(args: &GenomeArgs) -> Result<()> {
+    let index = load_index(args)?;
+
+    if let Some(genome) = &args.genome {
+        println!("Genome: {}", genome.display());
+    }
+    if let Some(path) = &args.index {
+        println!("Index: {}", path.display());
+    }
+    println!("Records: {}", index.reference().records.len());
+    for record in &index.reference().records {
+        println!("  {}\t{}", record.name, record.len);
+    }
+
+    let mut counts = [0; ALPHABET.len()];
+    for &c in &index.genome()[..index.genome().len() - 1] {
+        counts[c as usize] += 1;
+    }
+    println!("Length: {}", index.genome().len() - 1);
+    for (symbol, count) in ALPHABET.iter().zip(counts.iter()).skip(1) {
+        println!("  {}\t{}", symbol, count);
+    }
+    println!("  N/$\t{}", counts[0]);
+
+    println!("Suffix array: {} bytes", size_of_val(index.suffix_array()));
+    println!(
+        "O-table ({:?} layout, spacing {}): {} bytes",
+        index.o_table().layout(),
+        index.o_table().spacing(),
+        index.o_table().size_in_bytes()
+    );
+    println!(
+        "Wavelet tree: {} bytes",
+        wavelet_tree(index.o_table()).size_in_bytes()
+    );
+    println!("C-table: {} bytes", size_of_val(index.c_table()));
+    Ok(())
+}
+
+pub fn time_sais(args: &BenchArgs) -> Result<()> {
+    let (_, genome) = load_genome(&args.genome)?;
 
     let mut total = 0;
-    for _ in 0..iterations {
+    for _ in 0..args.iterations {
         let time = Instant::now();
         let sa = suffix_array_induced_sort(&genome);
         total += time.elapsed().as_nanos();
 
-        if output {
+        if !args.no_output {
             println!("Suffix array has length {}", sa.len());
         }
     }
 
-    println!("{}", total / iterations);
+    println!("{}", total / args.iterations);
+    Ok(())
 }
 
-pub fn time_skew(args: Vec<String>) {
-    let genome_file_name = &args[2];
-    let iterations: u128 = args[3].parse().unwrap();
-    let output = args
-        .iter()
-        .find(|s| *s == &"--no-output".to_owned())
-        .is_none();
-    let genome = read_and_remap_genome(genome_file_name);
+pub fn time_skew(args: &BenchArgs) -> Result<()> {
+    let (_, genome) = load_genome(&args.genome)?;
 
     let mut total = 0;
-    for _ in 0..iterations {
+    for _ in 0..args.iterations {
         let time = Instant::now();
         let sa = skew(&genome);
         total += time.elapsed().as_nanos();
 
-        if output {
+        if !args.no_output {
             println!("Suffix array has length {}", sa.len());
         }
     }
 
-    println!("{}", total / iterations);
+    println!("{}", total / args.iterations);
+    Ok(())
 }
 
-pub fn time_naive_sa(args: Vec<String>) {
-    let genome_file_name = &args[2];
-    let iterations: u128 = args[3].parse().unwrap();
-    let output = args
-        .iter()
-        .find(|s| *s == &"--no-output".to_owned())
-        .is_none();
-    let genome = read_and_remap_genome(genome_file_name);
+pub fn time_naive_sa(args: &BenchArgs) -> Result<()> {
+    let (_, genome) = load_genome(&args.genome)?;
 
     let mut total = 0;
-    for _ in 0..iterations {
+    for _ in 0..args.iterations {
         let time = Instant::now();
         let sa = construct_suffix_array_naive(&genome);
         total += time.elapsed().as_nanos();
 
-        if output {
+        if !args.no_output {
             println!("Suffix array has length {}", sa.len());
         }
     }
 
-    println!("{}", total / iterations);
+    println!("{}", total / args.iterations);
+    Ok(())
 }
 
-pub fn time_o_table(args: Vec<String>) {
-    let genome_file_name = &args[2];
-    let iterations = args[3].parse::<u128>().unwrap();
-    let spacing = args[4].parse::<usize>().unwrap();
-    let output = args
-        .iter()
-        .find(|s| *s == &"--no-output".to_owned())
-        .is_none();
-
-    let genome = read_and_remap_genome(genome_file_name);
-    let suffix_array = get_sa(genome_file_name, &genome, false);
+pub fn time_o_table(args: &BenchArgs) -> Result<()> {
+    let index = load_index(&args.genome)?;
 
     let mut total = 0;
-    for _ in 0..iterations {
+    for _ in 0..args.iterations {
         let time = Instant::now();
-        let o_table = OTable::new(&genome, &suffix_array, spacing);
+        let o_table = OTable::with_layout(
+            index.genome(),
+            index.suffix_array(),
+            index.o_table().spacing(),
+            index.o_table().layout(),
+        );
         total += time.elapsed().as_nanos();
 
-        if output {
+        if !args.no_output {
             println!("{:?}", o_table.shape())
         }
     }
 
-    println!("{}", total / iterations);
+    println!("{}", total / args.iterations);
+    Ok(())
 }
 
-pub fn time_approx(args: Vec<String>) {
-    let genome_file_name = &args[2];
-    let reads_file_name = &args[3];
-    let iterations = args[4].parse::<u128>().unwrap();
-    let spacing = args[5].parse::<usize>().unwrap();
-    let edits: usize = args[6].parse().unwrap();
-    let output = args
-        .iter()
-        .find(|s| *s == &"--no-output".to_owned())
-        .is_none();
-
-    let genome = read_genome(genome_file_name);
-    let remapped_genome = remap_reference(&genome);
-
-    let suffix_array = get_sa(genome_file_name, &remapped_genome, false);
-    let o_table = get_o_table(
-        genome_file_name,
-        &remapped_genome,
-        &suffix_array,
-        spacing,
-        false,
-    );
-    let c_table = generate_c_table(&remapped_genome);
-
-    let reverse_genome: String = genome.chars().rev().collect();
-    let reverse_remapped = remap_reference(&reverse_genome);
-
-    // TODO: Gem også reverse suffix array til disk
-    let reverse_suffix_array = get_sa(genome_file_name, &reverse_remapped, true);
-    let reverse_o_table = get_o_table(
-        &genome_file_name,
-        &reverse_remapped,
-        &reverse_suffix_array,
-        spacing,
-        true,
-    );
+pub fn time_sampled_sa(args: &BenchArgs) -> Result<()> {
+    let index = load_index(&args.genome)?;
+    let suffix_array = index.suffix_array();
+    let strategy = if args.by_rank {
+        SamplingStrategy::Rank
+    } else {
+        SamplingStrategy::TextPosition
+    };
+    let sampled = SampledSuffixArray::new(index.genome(), suffix_array, args.rate, strategy);
+    let lookup = sampled.with_tables(index.o_table(), index.c_table());
 
     let mut total = 0;
+    for _ in 0..args.iterations {
+        let time = Instant::now();
+        locate_interval(&lookup, 0, sampled.len(), None);
+        total += time.elapsed().as_nanos();
 
-    let reads = read_and_remap_reads(reads_file_name).unwrap();
-    for read in &reads {
-        let params = ApproxSearchParams {
-            reference: &remapped_genome,
-            query: read,
-            o_table: &o_table,
-            c_table: &c_table,
-            rev_o_table: &reverse_o_table,
-            edits,
-        };
+        if !args.no_output {
+            println!(
+                "Kept {} of {} suffix array entries",
+                sampled.sample_count(),
+                suffix_array.len()
+            );
+        }
+    }
 
-        for _ in 0..iterations {
-            let time = Instant::now();
-            let results = approx_search(params);
-            total += time.elapsed().as_nanos();
+    println!("{}", total / (args.iterations * sampled.len() as u128));
+    Ok(())
+}
 
-            if output {
-                println!("{:?}", results);
+/// The reads of a search benchmark. Clap makes `--reads` required for these.
+fn bench_reads(args: &BenchArgs) -> Result<Vec<ReadRecord>> {
+    match &args.reads {
+        Some(path) => read_reads_from_path(path),
+        None => Err(GeneSearchError::InvalidArgument(
+            "--reads is required to time a search".to_string(),
+        )),
+    }
+}
+
+/// A wavelet tree over the BWT of an O-table.
+fn wavelet_tree(o_table: &OTable) -> WaveletTree {
+    let bwt: Vec<u8> = (0..o_table.len()).map(|i| o_table.symbol(i)).collect();
+    WaveletTree::new(&bwt)
+}
+
+/// Wavelet trees of the forward and reverse BWT, when benchmarking over them
+/// instead of the O-tables.
+fn bench_wavelet_trees(args: &BenchArgs, index: &FmIndex) -> Option<(WaveletTree, WaveletTree)> {
+    match args.rank {
+        RankStructure::OTable => None,
+        RankStructure::WaveletTree => {
+            let trees = (
+                wavelet_tree(index.o_table()),
+                wavelet_tree(index.reverse_o_table()),
+            );
+            if !args.no_output {
+                println!("Wavelet tree: {} bytes", trees.0.size_in_bytes());
             }
+            Some(trees)
         }
     }
+}
 
-    println!("{}", total / (iterations * reads.len() as u128));
+/// Approximate search with a search scheme, or by backtracking without one,
+/// with the counters of the search.
+fn bench_approx_search<R: Rank + ?Sized>(
+    params: ApproxSearchParams<R>,
+    scheme: Option<&SearchScheme>,
+) -> (ApproxAlignments, SearchStats) {
+    match scheme {
+        Some(scheme) => scheme_search_with_stats(params, scheme),
+        None => approx_search_with_stats(params),
+    }
 }
 
-pub fn time_exact_bwt(args: Vec<String>) {
-    let genome_file_name = &args[2];
-    let reads_file_name = &args[3];
-    let iterations = args[4].parse::<u128>().unwrap();
-    let spacing = args[5].parse::<usize>().unwrap();
-    let output = args
+pub fn time_approx(args: &BenchArgs) -> Result<()> {
+    let index = load_index(&args.genome)?;
+    let reads = bench_reads(args)?;
+    let queries: Vec<Vec<u8>> = reads
         .iter()
-        .find(|s| *s == &"--no-output".to_owned())
-        .is_none();
-
-    let genome = read_and_remap_genome(genome_file_name);
-    let suffix_array = get_sa(genome_file_name, &genome, false);
-    let o_table = get_o_table(genome_file_name, &genome, &suffix_array, spacing, false);
-    let c_table = generate_c_table(&genome);
+        .map(|read| remap_query(&read.sequence))
+        .collect::<Result<_>>()?;
+    let trees = bench_wavelet_trees(args, &index);
+    let scheme = search_scheme(args.scheme, args.edits)?;
+    let model = edit_model(args.distance);
 
     let mut total = 0;
-    let reads = read_and_remap_reads(reads_file_name).unwrap();
-    for read in &reads {
-        for _ in 0..iterations {
-            let time = Instant::now();
-            let result = bwt_search(read, &o_table, &c_table);
-            total += time.elapsed().as_nanos();
+    for _ in 0..args.iterations {
+        let time = Instant::now();
+        let batch_results = match &trees {
+            Some((tree, reverse_tree)) => map_in_order(&queries, args.threads, |query| {
+                let params = ApproxSearchParams {
+                    edit_model: model,
+                    ..index.approx_params_over(tree, reverse_tree, query, args.edits, 1)
+                };
+                bench_approx_search(params, scheme.as_ref())
+            }),
+            None => index.map_reads(&queries, args.threads, |index, query| {
+                let params = ApproxSearchParams {
+                    edit_model: model,
+                    ..index.approx_params(query, args.edits, 1)
+                };
+                bench_approx_search(params, scheme.as_ref())
+            }),
+        };
+        total += time.elapsed().as_nanos();
 
-            if output {
-                println!("{:?}", result);
+        if !args.no_output {
+            let mut stats = SearchStats::default();
+            for (results, read_stats) in &batch_results {
+                stats.merge(*read_stats);
+                for (left, right, cigar, edits) in results {
+                    let positions = locate_interval(index.suffix_array(), *left, *right, None);
+                    println!(
+                        "{} {} at {:?}",
+                        cigar,
+                        edits,
+                        index.reference().resolve_all(&positions)
+                    );
+                }
             }
+            println!(
+                "Visited {} nodes per read, {} pruned, at most {} on the stack",
+                stats.visited / reads.len().max(1),
+                stats.pruned / reads.len().max(1),
+                stats.max_stack
+            );
         }
     }
 
-    println!("{}", total / iterations);
+    println!("{}", total / (args.iterations * reads.len().max(1) as u128));
+    Ok(())
 }
 
-pub fn time_exact_binary(args: Vec<String>) {
-    let genome_file_name = &args[2];
-    let reads_file_name = &args[3];
-    let iterations = args[4].parse::<u128>().unwrap();
-    let output = args
+pub fn time_exact_bwt(args: &BenchArgs) -> Result<()> {
+    let index = load_index(&args.genome)?;
+    let reads = bench_reads(args)?;
+    let queries: Vec<Vec<u8>> = reads
         .iter()
-        .find(|s| *s == &"--no-output".to_owned())
-        .is_none();
+        .map(|read| remap_query(&read.sequence))
+        .collect::<Result<_>>()?;
+    let trees = bench_wavelet_trees(args, &index);
 
-    let genome = read_and_remap_genome(genome_file_name);
-    let suffix_array = get_sa(genome_file_name, &genome, false);
+    let mut total = 0;
+    for _ in 0..args.iterations {
+        let time = Instant::now();
+        let intervals = match &trees {
+            Some((tree, _)) => map_in_order(&queries, args.threads, |query| {
+                bwt_search(query, tree, index.c_table())
+            }),
+            None => index.map_reads(&queries, args.threads, |index, query| {
+                bwt_search(query, index.o_table(), index.c_table())
+            }),
+        };
+        total += time.elapsed().as_nanos();
+
+        if !args.no_output {
+            for interval in intervals {
+                let positions = locate_exact(index.suffix_array(), interval, None);
+                println!("{:?}", index.reference().resolve_all(&positions));
+            }
+        }
+    }
+
+    println!("{}", total / args.iterations);
+    Ok(())
+}
+
+pub fn time_exact_binary(args: &BenchArgs) -> Result<()> {
+    let index = load_index(&args.genome)?;
 
     let mut total = 0;
-    let reads = read_and_remap_reads(reads_file_name).unwrap();
+    let reads = bench_reads(args)?;
     for read in &reads {
-        for _ in 0..iterations {
+        let query = remap_query(&read.sequence)?;
+        for _ in 0..args.iterations {
             let time = Instant::now();
-            let result = exact_binary_search(&genome, &suffix_array, read);
+            let result = exact_binary_search(index.genome(), index.suffix_array(), &query);
             total += time.elapsed().as_nanos();
 
-            if output {
+            if !args.no_output {
                 println!("{:?}", result);
             }
         }
     }
 
-    println!("{}", total / iterations);
+    println!("{}", total / args.iterations);
+    Ok(())
 }
 
-pub fn log_performance() -> std::io::Result<()> {
+pub fn log_performance() -> Result<()> {
     // Ensure that the directory exists
     let results_path = Path::new("./results/");
     if !results_path.exists() {
-        match create_dir(results_path) {
-            Ok(_) => println!("Created \"results\" directory"),
-            Err(error) => panic!("Could not create directory: {}", error),
-        }
+        create_dir(results_path)?;
+        println!("Created \"results\" directory");
     }
 
     // Create the file name
@@ -284,10 +751,10 @@ pub fn log_performance() -> std::io::Result<()> {
     let mut file = File::create(filename)?;
 
     // Read the genome file
-    let genome_string = try_read_genome(HG38_1000)?;
+    let genome_string = read_genome(HG38_1000)?;
 
     // Run the algs
-    let genome = remap_reference(&genome_string);
+    let genome = remap_reference(&genome_string)?;
 
     // Initialize suffix array, O-table, and C-table
     let suff_and_table_start = Instant::now();
@@ -300,7 +767,8 @@ pub fn log_performance() -> std::io::Result<()> {
     let suff_and_table_time = suff_and_table_start.elapsed().as_nanos();
     println!("Finished generating tables...");
 
-    let query = remap_query("AATAAACCTTACCTAGCACTCCATCATGTCTTATGGCGCGTGATTTGCCCCGGACTCAGGCAAAACCC");
+    let query =
+        remap_query("AATAAACCTTACCTAGCACTCCATCATGTCTTATGGCGCGTGATTTGCCCCGGACTCAGGCAAAACCC")?;
 
     //search with bwt exact search
     let exact_time_start = Instant::now();
@@ -310,10 +778,7 @@ pub fn log_performance() -> std::io::Result<()> {
     let exact_time = exact_time_start.elapsed().as_nanos();
     println!("Finished exact search...");
 
-    let mut exact_matches = vec![];
-    for item in suffix_array.iter().take((exact_end) + 1).skip(exact_start) {
-        exact_matches.push(item);
-    }
+    let exact_matches = locate_exact(&suffix_array, (exact_start, exact_end), None);
 
     //approx search
     let approx_start = Instant::now();
@@ -330,6 +795,9 @@ pub fn log_performance() -> std::io::Result<()> {
         c_table: &c_table,
         rev_o_table: &reverse_o_table,
         edits: 1,
+        edit_model: EditModel::Levenshtein,
+        wildcard_cost: 1,
+        scoring: None,
     };
 
     let approx_search_result = approx_search(params);
@@ -339,34 +807,16 @@ pub fn log_performance() -> std::io::Result<()> {
 
     let mut approx_matches = vec![];
     for (start, end, cigar, edits) in approx_search_result.iter() {
-        let mut indices = vec![];
-        for item in suffix_array.iter().take(*end).skip(*start) {
-            indices.push(item);
-        }
-
-        let mut formatted_cigar = String::new();
-        let mut cigar_format_buffer: (usize, char) = (1, cigar.chars().next().unwrap());
-        for i in 1..cigar.len() {
-            let (num, curr_char) = cigar_format_buffer;
-            let c = cigar.chars().nth(i).unwrap();
-            if c == curr_char {
-                cigar_format_buffer = (num + 1, curr_char);
-            } else {
-                formatted_cigar.push_str(&format!("{}{}", num, curr_char));
-                cigar_format_buffer = (1, c)
-            }
-        }
-        formatted_cigar.push_str(&format!(
-            "{}{}",
-            cigar_format_buffer.0, cigar_format_buffer.1
-        ));
+        let indices = locate_interval(&suffix_array, *start, *end, None);
 
         approx_matches.push(format!(
             "
     Matches on indices: {:?}
     Cigar for matches: {:?}
     Edits: {}",
-            indices, formatted_cigar, edits
+            indices,
+            cigar.to_sam(),
+            edits
         ));
     }
 
@@ -377,6 +827,7 @@ Searching for a string with length: {}\n
 Table generation took {} ns ({} s) 
 Exact Search took {} ns ({} s) and yielded {:?} 
 Approx search took {} ns ({} s) and yielded {} \n
+    All approx matches on indices: {:?}\n
     Total execution time: {} s
     ",
         genome.len(),
@@ -389,6 +840,7 @@ Approx search took {} ns ({} s) and yielded {} \n
         approx_time,
         approx_time / 1000000000,
         approx_matches.join("\n"),
+        locate_approx(&suffix_array, &approx_search_result, None),
         (suff_and_table_time + exact_time + approx_time) / 1000000000
     );
 
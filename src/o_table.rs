@@ -1,34 +1,102 @@
-use crate::{bwt, ALPHABET};
-use std::io::{BufReader, Write};
-use std::{
-    fmt::{Display, Formatter},
-    fs::File,
-    path::Path,
-};
-
-pub struct OTable<'a> {
-    array: Vec<usize>,
+use crate::packed_bwt::{InterleavedBwt, PackedBwt};
+use crate::rank::Rank;
+use crate::storage::Storage;
+use crate::util::bwt;
+use crate::ALPHABET;
+use std::fmt::{Display, Formatter};
+
+/// How the sampled counts of an O-table are laid out in memory.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OTableLayout {
+    /// A row of sampled counts per symbol, next to the packed BWT.
+    Separate,
+    /// The counts of every symbol stored with the block of the BWT after them,
+    /// with every block starting on a cache line. The spacing is rounded up to
+    /// a multiple of 64, and at 64 a lookup touches exactly one line.
+    Interleaved,
+}
+
+impl OTableLayout {
+    /// The number identifying the layout in index files.
+    pub fn code(self) -> u32 {
+        match self {
+            OTableLayout::Separate => 0,
+            OTableLayout::Interleaved => 1,
+        }
+    }
+
+    pub fn from_code(code: u32) -> Option<Self> {
+        match code {
+            0 => Some(OTableLayout::Separate),
+            1 => Some(OTableLayout::Interleaved),
+            _ => None,
+        }
+    }
+}
+
+/// The tables of an O-table in one of the layouts.
+pub enum OTableParts {
+    Separate {
+        array: Storage<usize>,
+        bwt: PackedBwt,
+    },
+    Interleaved(InterleavedBwt),
+}
+
+impl OTableParts {
+    /// Copies mapped tables onto the heap.
+    pub fn into_owned(self) -> Self {
+        match self {
+            OTableParts::Separate { array, bwt } => OTableParts::Separate {
+                array: array.into_owned().into(),
+                bwt: bwt.into_owned(),
+            },
+            OTableParts::Interleaved(bwt) => OTableParts::Interleaved(bwt.into_owned()),
+        }
+    }
+}
+
+pub struct OTable {
     spacing: usize,
-    string: &'a [u8],
-    suffix_array: &'a [usize],
+    parts: OTableParts,
 }
 
-impl<'a> OTable<'a> {
+impl OTable {
     /// Allocate and generate an O-table.
-    pub fn new(string: &'a [u8], suffix_array: &'a [usize], spacing: usize) -> Self {
+    pub fn new(string: &[u8], suffix_array: &[usize], spacing: usize) -> Self {
+        OTable::with_layout(string, suffix_array, spacing, OTableLayout::Separate)
+    }
+
+    /// Allocate and generate an O-table with the given layout.
+    pub fn with_layout(
+        string: &[u8],
+        suffix_array: &[usize],
+        spacing: usize,
+        layout: OTableLayout,
+    ) -> Self {
+        let bwt = build_bwt(string, suffix_array);
+        if layout == OTableLayout::Interleaved {
+            let bwt = InterleavedBwt::new(&bwt, spacing);
+            return OTable {
+                spacing,
+                parts: OTableParts::Interleaved(bwt),
+            };
+        }
+
         let array_len = ((string.len() / spacing) + 1) * ALPHABET.len();
         let mut o_table = OTable {
-            array: vec![0; array_len],
             spacing,
-            string,
-            suffix_array,
+            parts: OTableParts::Separate {
+                array: vec![0; array_len].into(),
+                bwt: PackedBwt::new(&bwt),
+            },
         };
 
         // Fill O-table
         let (_, cols) = o_table.shape();
-        let mut counter = vec![0; 5];
+        let mut counter = [0; 5];
         for i in 1..cols {
-            let c = bwt(o_table.string, o_table.suffix_array, i - 1);
+            let c = bwt[i - 1];
             counter[c as usize] += 1;
             if i % spacing == 0 {
                 for (a, &c) in counter.iter().enumerate() {
@@ -40,39 +108,36 @@ impl<'a> OTable<'a> {
         o_table
     }
 
-    // Reads an O-table from a given file
-    pub fn from_file(
-        filename: &str,
-        string: &'a [u8],
-        suffix_array: &'a [usize],
-        spacing: usize,
-    ) -> Self {
-        let mut otable_path = Path::new("resources/otable/").join(filename);
-        otable_path.set_extension(spacing.to_string());
-        match File::open(&otable_path) {
-            Ok(f) => {
-                let buf_reader = BufReader::new(f);
-                let decoded: Vec<usize> = bincode::deserialize_from(buf_reader).unwrap();
-                OTable {
-                    array: decoded,
-                    spacing,
-                    string: &string,
-                    suffix_array: &suffix_array,
-                }
-            }
-            Err(_) => {
-                // File doesn't exist, generate it
-                let o_table = OTable::new(&string, &suffix_array, spacing);
-                let array = o_table.array.clone();
-                let bytes: Vec<u8> = bincode::serialize(&array).unwrap();
-                let mut file = File::create(&otable_path).unwrap();
-                file.write_all(&bytes).unwrap();
-                o_table
-            }
+    /// Rebuilds an O-table from the tables of another one, which may be sections
+    /// of a memory-mapped index file.
+    pub fn from_parts(spacing: usize, parts: OTableParts) -> Self {
+        OTable { spacing, parts }
+    }
+
+    /// The tables, as accepted by `from_parts`.
+    pub fn parts(&self) -> &OTableParts {
+        &self.parts
+    }
+
+    /// Takes apart the O-table, keeping its storage.
+    pub fn into_parts(self) -> OTableParts {
+        self.parts
+    }
+
+    pub fn layout(&self) -> OTableLayout {
+        match self.parts {
+            OTableParts::Separate { .. } => OTableLayout::Separate,
+            OTableParts::Interleaved(_) => OTableLayout::Interleaved,
         }
     }
 
-    /// Calculates the index into the internal array.
+    /// The requested distance between sampled counts. The interleaved layout
+    /// samples at the start of every block, which may be further apart.
+    pub fn spacing(&self) -> usize {
+        self.spacing
+    }
+
+    /// Calculates the index into the internal array of the separate layout.
     /// Returns a two values:
     /// - the first is the index into the internal array
     /// - the second is the remaining lines to count the character in
@@ -86,50 +151,98 @@ impl<'a> OTable<'a> {
                 a, i, rows, cols,
             )
         }
-        let offset = a * self.array.len() / ALPHABET.len();
+        let array_len = match &self.parts {
+            OTableParts::Separate { array, .. } => array.len(),
+            OTableParts::Interleaved(_) => unreachable!("interleaved O-tables have no array"),
+        };
+        let offset = a * array_len / ALPHABET.len();
 
         (offset + i / self.spacing, i % self.spacing)
     }
 
     pub fn shape(&self) -> (usize, usize) {
-        (ALPHABET.len(), self.string.len() + 1)
+        let len = match &self.parts {
+            OTableParts::Separate { bwt, .. } => bwt.len(),
+            OTableParts::Interleaved(bwt) => bwt.len(),
+        };
+        (ALPHABET.len(), len + 1)
     }
 
-    fn find_count(&self, from: usize, to: usize, character: u8) -> usize {
-        let mut count = 0;
-
-        for i in from..to {
-            if character == bwt(self.string, self.suffix_array, i) {
-                count += 1;
+    /// Memory used by the sampled counts and the packed BWT.
+    pub fn size_in_bytes(&self) -> usize {
+        match &self.parts {
+            OTableParts::Separate { array, bwt } => {
+                array.len() * std::mem::size_of::<usize>() + bwt.size_in_bytes()
             }
+            OTableParts::Interleaved(bwt) => bwt.size_in_bytes(),
         }
-        count
     }
 
+    /// The symbol in the i'th position of the Burrows-Wheeler transform.
+    pub fn bwt_symbol(&self, i: usize) -> u8 {
+        match &self.parts {
+            OTableParts::Separate { bwt, .. } => bwt.get(i),
+            OTableParts::Interleaved(bwt) => bwt.get(i),
+        }
+    }
+
+    /// Counts the occurrences of `a` before position `i` of the BWT, from the
+    /// nearest sample and a popcount over the packed BWT after it.
     pub fn get(&self, a: u8, i: usize) -> usize {
-        match self.calc_index(a, i) {
-            (idx, 0) => self.array[idx],
-            (idx, _) => self.array[idx] + self.find_count(i - (i % self.spacing), i, a),
+        match &self.parts {
+            OTableParts::Separate { array, bwt } => match self.calc_index(a, i) {
+                (idx, 0) => array[idx],
+                (idx, _) => array[idx] + bwt.rank(a, i - (i % self.spacing), i),
+            },
+            OTableParts::Interleaved(bwt) => bwt.occurrences(a, i),
         }
     }
 
     fn set(&mut self, a: u8, i: usize, v: usize) {
-        match self.calc_index(a, i) {
-            (idx, 0) => self.array[idx] = v,
+        let idx = match self.calc_index(a, i) {
+            (idx, 0) => idx,
             _ => todo!(),
+        };
+        if let OTableParts::Separate { array, .. } = &mut self.parts {
+            array[idx] = v;
         }
     }
 }
 
-impl<'a> Display for OTable<'a> {
+impl Rank for OTable {
+    fn rank(&self, a: u8, i: usize) -> usize {
+        self.get(a, i)
+    }
+
+    fn symbol(&self, i: usize) -> u8 {
+        self.bwt_symbol(i)
+    }
+
+    fn len(&self) -> usize {
+        self.shape().1 - 1
+    }
+
+    fn size_in_bytes(&self) -> usize {
+        OTable::size_in_bytes(self)
+    }
+}
+
+/// Materializes the Burrows-Wheeler transform of a string from its suffix array.
+fn build_bwt(string: &[u8], suffix_array: &[usize]) -> Vec<u8> {
+    (0..suffix_array.len())
+        .map(|i| bwt(string, suffix_array, i))
+        .collect()
+}
+
+impl Display for OTable {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         // Header row
         write!(f, "{:>3}", ' ')?;
         write!(f, "{:>3}", ' ')?;
 
-        for i in 0..self.suffix_array.len() {
-            let j = bwt(self.string, self.suffix_array, i);
-            let c = ALPHABET[j as usize];
+        let (_, cols) = self.shape();
+        for j in 0..cols - 1 {
+            let c = ALPHABET[self.bwt_symbol(j) as usize];
             write!(f, "{:>3}", c)?;
         }
         writeln!(f,)?;
@@ -137,7 +250,7 @@ impl<'a> Display for OTable<'a> {
         // Data rows
         for (i, c) in ALPHABET.iter().enumerate() {
             write!(f, "{:>3}", c)?;
-            for j in 0..(self.string.len() + 1) {
+            for j in 0..cols {
                 write!(f, "{:>3}", self.get(i as u8, j))?;
             }
             writeln!(f,)?;
@@ -154,7 +267,7 @@ mod tests {
 
     #[test]
     fn test_o_table_shape() {
-        let reference = remap_reference("ACGTATCGTGACGGGCTATAGCGATGTCGATGC");
+        let reference = remap_reference("ACGTATCGTGACGGGCTATAGCGATGTCGATGC").unwrap();
         let sa = suffix_array_induced_sort(&reference);
         let o_table = OTable::new(&reference, &sa, 10);
         let (rows, cols) = o_table.shape();
@@ -173,7 +286,7 @@ mod tests {
         G | 0  0  1  1  1  1  1  2  3  4  4  5  5  5  5  6  6  6  6  6  6  7  8  8  8  8  8  8  8  9 10 10 11 11 11
         T | 0  0  0  0  1  2  3  3  3  3  4  4  4  4  5  5  6  6  6  7  7  7  7  7  7  8  8  8  8  8  8  8  8  8  8
         */
-        let reference = remap_reference("ACGTATCGTGACGGGCTATAGCGATGTCGATGC");
+        let reference = remap_reference("ACGTATCGTGACGGGCTATAGCGATGTCGATGC").unwrap();
         let sa = suffix_array_induced_sort(&reference);
         let o_table = OTable::new(&reference, &sa, 10);
         println!("{:>6}{:>30}{:>30}{:>30}", 'x', 'x', 'x', 'x');
@@ -186,9 +299,25 @@ mod tests {
         assert_eq!(o_table.get(1, 30), 4);
     }
 
+    #[test]
+    fn test_o_table_get_with_large_spacing() {
+        let reference =
+            remap_reference("ACGTNNATCGTGACGGGCTATAGCGANNNTGTCGATGCAATTGGCCATGCAGTNACGT").unwrap();
+        let sa = suffix_array_induced_sort(&reference);
+        let dense = OTable::new(&reference, &sa, 1);
+        let sparse = OTable::new(&reference, &sa, 100);
+
+        let (rows, cols) = dense.shape();
+        for a in 0..rows as u8 {
+            for i in 0..cols {
+                assert_eq!(sparse.get(a, i), dense.get(a, i));
+            }
+        }
+    }
+
     #[test]
     fn test_calc_index() {
-        let reference = remap_reference("ACGTATCGTGACGGGCTATAGCGATGTCGATGC");
+        let reference = remap_reference("ACGTATCGTGACGGGCTATAGCGATGTCGATGC").unwrap();
         let sa = suffix_array_induced_sort(&reference);
         let o_table = OTable::new(&reference, &sa, 10);
         assert_eq!((0, 0), o_table.calc_index(0, 0));
@@ -0,0 +1,346 @@
+use crate::storage::{CacheLine, Storage};
+use crate::ALPHABET;
+
+/// Number of 2-bit symbols in a word.
+pub(crate) const SYMBOLS_PER_WORD: usize = 32;
+
+/// Every second bit set, selecting the low bit of each 2-bit symbol.
+const LOW_BITS: u64 = 0x5555_5555_5555_5555;
+
+/// A Burrows-Wheeler transform over the remapped alphabet, packed into 2 bits per
+/// symbol, with the `$` positions in a separate bit vector. `$` is stored with
+/// the code of A, and subtracted again when counting A.
+///
+/// Counting a symbol in a range takes one XOR and popcount per 32 positions, so
+/// the O-table can sample its counts far apart.
+pub struct PackedBwt {
+    len: usize,
+    /// Symbol `c` in bits `2 * i` and `2 * i + 1` of word `i / 32` as `c - 1`
+    symbols: Storage<u64>,
+    /// Bit `i % 64` of word `i / 64` is set where the symbol is `$`
+    sentinels: Storage<u64>,
+}
+
+impl PackedBwt {
+    /// Packs a BWT over the remapped alphabet.
+    pub fn new(bwt: &[u8]) -> Self {
+        let mut symbols = vec![0; bwt.len().div_ceil(SYMBOLS_PER_WORD)];
+        let mut sentinels = vec![0; bwt.len().div_ceil(64)];
+        pack(bwt, &mut symbols, &mut sentinels);
+
+        PackedBwt {
+            len: bwt.len(),
+            symbols: symbols.into(),
+            sentinels: sentinels.into(),
+        }
+    }
+
+    /// Rebuilds a packed BWT from the length and words of another one.
+    pub fn from_parts(
+        len: usize,
+        symbols: impl Into<Storage<u64>>,
+        sentinels: impl Into<Storage<u64>>,
+    ) -> Self {
+        PackedBwt {
+            len,
+            symbols: symbols.into(),
+            sentinels: sentinels.into(),
+        }
+    }
+
+    /// The length, the packed symbols and the `$` bit vector, as accepted by `from_parts`.
+    pub fn parts(&self) -> (usize, &[u64], &[u64]) {
+        (self.len, &self.symbols, &self.sentinels)
+    }
+
+    /// Copies mapped words onto the heap.
+    pub fn into_owned(self) -> Self {
+        PackedBwt::from_parts(
+            self.len,
+            self.symbols.into_owned(),
+            self.sentinels.into_owned(),
+        )
+    }
+
+    /// Whether the words are read from a memory-mapped index file.
+    pub fn is_mapped(&self) -> bool {
+        self.symbols.is_mapped()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn size_in_bytes(&self) -> usize {
+        (self.symbols.len() + self.sentinels.len()) * std::mem::size_of::<u64>()
+    }
+
+    /// The symbol at position `i`.
+    pub fn get(&self, i: usize) -> u8 {
+        symbol(&self.symbols, &self.sentinels, i)
+    }
+
+    /// Counts the occurrences of `a` in positions `from..to`.
+    pub fn rank(&self, a: u8, from: usize, to: usize) -> usize {
+        rank(&self.symbols, &self.sentinels, a, from, to)
+    }
+}
+
+/// Positions covered by the smallest block of an `InterleavedBwt`.
+pub const BLOCK_SYMBOLS: usize = 64;
+
+/// A packed BWT with the sampled O-table counts of every symbol stored right
+/// before the block of symbols they precede. A rank query reads one block instead
+/// of a row per symbol and the BWT. Every block starts on a cache line, and a
+/// block of 64 positions takes 8 words, so it fills exactly one line.
+pub struct InterleavedBwt {
+    len: usize,
+    /// Positions per block, a multiple of `BLOCK_SYMBOLS`
+    block_len: usize,
+    /// Per block: the counts of each symbol before it, its packed symbols and its
+    /// `$` bit vector, laid out as in `PackedBwt` and padded to whole lines
+    lines: Storage<CacheLine>,
+}
+
+impl InterleavedBwt {
+    /// Packs a BWT into blocks of `spacing` positions, rounded up to a multiple
+    /// of `BLOCK_SYMBOLS`.
+    pub fn new(bwt: &[u8], spacing: usize) -> Self {
+        let block_len = spacing.div_ceil(BLOCK_SYMBOLS) * BLOCK_SYMBOLS;
+        let mut interleaved = InterleavedBwt {
+            len: bwt.len(),
+            block_len,
+            lines: Vec::new().into(),
+        };
+
+        let stride = interleaved.stride();
+        let mut lines = vec![CacheLine::default(); InterleavedBwt::lines_len(bwt.len(), block_len)];
+        let mut counts = [0; ALPHABET.len()];
+        for (block, words) in CacheLine::words_mut(&mut lines)
+            .chunks_exact_mut(stride)
+            .enumerate()
+        {
+            let (block_counts, packed) = words.split_at_mut(ALPHABET.len());
+            let (symbols, sentinels) = packed.split_at_mut(block_len / SYMBOLS_PER_WORD);
+            let sentinels = &mut sentinels[..block_len / 64];
+            block_counts.copy_from_slice(&counts);
+
+            let start = (block * block_len).min(bwt.len());
+            let block_bwt = &bwt[start..(start + block_len).min(bwt.len())];
+            pack(block_bwt, symbols, sentinels);
+            for &c in block_bwt {
+                counts[c as usize] += 1;
+            }
+        }
+
+        interleaved.lines = lines.into();
+        interleaved
+    }
+
+    /// Rebuilds an interleaved BWT from the parts of another one.
+    pub fn from_parts(len: usize, block_len: usize, lines: impl Into<Storage<CacheLine>>) -> Self {
+        InterleavedBwt {
+            len,
+            block_len,
+            lines: lines.into(),
+        }
+    }
+
+    /// The length, the block length and the lines, as accepted by `from_parts`.
+    pub fn parts(&self) -> (usize, usize, &[CacheLine]) {
+        (self.len, self.block_len, &self.lines)
+    }
+
+    /// Copies mapped lines onto the heap.
+    pub fn into_owned(self) -> Self {
+        InterleavedBwt::from_parts(self.len, self.block_len, self.lines.into_owned())
+    }
+
+    /// Whether the lines are read from a memory-mapped index file.
+    pub fn is_mapped(&self) -> bool {
+        self.lines.is_mapped()
+    }
+
+    /// The number of lines a BWT of `len` symbols takes in blocks of `block_len`.
+    pub fn lines_len(len: usize, block_len: usize) -> usize {
+        (len / block_len + 1) * (InterleavedBwt::block_stride(block_len) / 8)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn block_len(&self) -> usize {
+        self.block_len
+    }
+
+    pub fn size_in_bytes(&self) -> usize {
+        self.lines.len() * std::mem::size_of::<CacheLine>()
+    }
+
+    /// Words per block, rounded up to whole lines.
+    fn block_stride(block_len: usize) -> usize {
+        (ALPHABET.len() + block_len / SYMBOLS_PER_WORD + block_len / 64).div_ceil(8) * 8
+    }
+
+    fn stride(&self) -> usize {
+        InterleavedBwt::block_stride(self.block_len)
+    }
+
+    /// The counts, packed symbols and `$` bit vector of the block holding position `i`.
+    fn block(&self, i: usize) -> (&[u64], &[u64], &[u64]) {
+        let stride = self.stride();
+        let start = i / self.block_len * stride;
+        let words = &CacheLine::words(&self.lines)[start..start + stride];
+        let (counts, packed) = words.split_at(ALPHABET.len());
+        let (symbols, sentinels) = packed.split_at(self.block_len / SYMBOLS_PER_WORD);
+        (counts, symbols, &sentinels[..self.block_len / 64])
+    }
+
+    /// The symbol at position `i`.
+    pub fn get(&self, i: usize) -> u8 {
+        let (_, symbols, sentinels) = self.block(i);
+        symbol(symbols, sentinels, i % self.block_len)
+    }
+
+    /// Counts the occurrences of `a` before position `i`.
+    pub fn occurrences(&self, a: u8, i: usize) -> usize {
+        let (counts, symbols, sentinels) = self.block(i);
+        counts[a as usize] as usize + rank(symbols, sentinels, a, 0, i % self.block_len)
+    }
+}
+
+/// Packs symbols over the remapped alphabet into zeroed words, as in `PackedBwt`.
+pub(crate) fn pack(bwt: &[u8], symbols: &mut [u64], sentinels: &mut [u64]) {
+    for (i, &c) in bwt.iter().enumerate() {
+        if c == 0 {
+            sentinels[i / 64] |= 1 << (i % 64);
+        } else {
+            symbols[i / SYMBOLS_PER_WORD] |= (c as u64 - 1) << (2 * (i % SYMBOLS_PER_WORD));
+        }
+    }
+}
+
+/// Counts the occurrences of `a` in positions `from..to` of packed symbols and
+/// their `$` bit vector, laid out as in `PackedBwt`.
+pub(crate) fn rank(symbols: &[u64], sentinels: &[u64], a: u8, from: usize, to: usize) -> usize {
+    match a {
+        0 => count_sentinels(sentinels, from, to),
+        1 => count_code(symbols, 0, from, to) - count_sentinels(sentinels, from, to),
+        _ => count_code(symbols, a as u64 - 1, from, to),
+    }
+}
+
+/// The symbol at position `i` of packed symbols and their `$` bit vector.
+pub(crate) fn symbol(symbols: &[u64], sentinels: &[u64], i: usize) -> u8 {
+    if sentinels[i / 64] >> (i % 64) & 1 == 1 {
+        0
+    } else {
+        let word = symbols[i / SYMBOLS_PER_WORD];
+        (word >> (2 * (i % SYMBOLS_PER_WORD)) & 3) as u8 + 1
+    }
+}
+
+fn count_code(symbols: &[u64], code: u64, from: usize, to: usize) -> usize {
+    let pattern = code * LOW_BITS;
+    let mut count = 0;
+    let mut i = from;
+    while i < to {
+        let word = i / SYMBOLS_PER_WORD;
+        let start = i % SYMBOLS_PER_WORD;
+        let end = (to - word * SYMBOLS_PER_WORD).min(SYMBOLS_PER_WORD);
+
+        // Both bits of a symbol are 0 after the XOR if it has the code
+        let x = symbols[word] ^ pattern;
+        let matches = !(x | x >> 1) & LOW_BITS;
+        count += (matches & bit_range(2 * start, 2 * end)).count_ones() as usize;
+        i = word * SYMBOLS_PER_WORD + end;
+    }
+    count
+}
+
+fn count_sentinels(sentinels: &[u64], from: usize, to: usize) -> usize {
+    let mut count = 0;
+    let mut i = from;
+    while i < to {
+        let word = i / 64;
+        let end = (to - word * 64).min(64);
+        count += (sentinels[word] & bit_range(i % 64, end)).count_ones() as usize;
+        i = word * 64 + end;
+    }
+    count
+}
+
+/// A word with bits `from..to` set.
+fn bit_range(from: usize, to: usize) -> u64 {
+    let below = |n: usize| if n >= 64 { !0 } else { (1 << n) - 1 };
+    below(to) & !below(from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn test_get_and_rank() {
+        let mut rng = StdRng::seed_from_u64(7);
+        // Mostly bases, with runs of $ like those from N in a genome
+        let bwt: Vec<u8> = (0..300)
+            .map(|i| if i % 50 < 4 { 0 } else { rng.gen_range(0..5) })
+            .collect();
+        let packed = PackedBwt::new(&bwt);
+
+        assert_eq!(packed.len(), bwt.len());
+        for (i, &c) in bwt.iter().enumerate() {
+            assert_eq!(packed.get(i), c);
+        }
+        for &(from, to) in &[(0, 0), (0, 300), (3, 31), (30, 97), (64, 128), (250, 300)] {
+            for a in 0..5 {
+                let expected = bwt[from..to].iter().filter(|&&c| c == a).count();
+                assert_eq!(
+                    packed.rank(a, from, to),
+                    expected,
+                    "{} in {}..{}",
+                    a,
+                    from,
+                    to
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_interleaved_occurrences() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let bwt: Vec<u8> = (0..500).map(|_| rng.gen_range(0..5)).collect();
+
+        for &spacing in &[1, 64, 100, 256] {
+            let interleaved = InterleavedBwt::new(&bwt, spacing);
+            assert_eq!(interleaved.block_len() % BLOCK_SYMBOLS, 0);
+            let lines = interleaved.parts().2;
+            assert_eq!(
+                lines.len(),
+                InterleavedBwt::lines_len(bwt.len(), interleaved.block_len())
+            );
+            assert_eq!(lines.as_ptr() as usize % 64, 0);
+            for i in 0..=bwt.len() {
+                if i < bwt.len() {
+                    assert_eq!(interleaved.get(i), bwt[i]);
+                }
+                for a in 0..5 {
+                    let expected = bwt[..i].iter().filter(|&&c| c == a).count();
+                    assert_eq!(interleaved.occurrences(a, i), expected);
+                }
+            }
+        }
+    }
+}
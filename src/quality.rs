@@ -0,0 +1,154 @@
+use crate::cigar::{Cigar, CigarOp};
+use crate::util::WILDCARD;
+
+/// Offset of the Phred qualities in FASTQ quality strings.
+pub const PHRED_OFFSET: u8 = 33;
+
+/// The Phred qualities of a FASTQ quality string.
+pub fn phred_qualities(quality: &str) -> Vec<u8> {
+    quality
+        .bytes()
+        .map(|q| q.saturating_sub(PHRED_OFFSET))
+        .collect()
+}
+
+/// Penalties of the edits of an alignment, with mismatches weighted by the
+/// quality of the read base, as in Bowtie 2. A mismatch at a base the sequencer
+/// was unsure of is likely a sequencing error, so it costs less.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Penalties {
+    /// Penalty of a mismatch at a base of quality 0
+    pub min_mismatch: usize,
+    /// Penalty of a mismatch at a base of quality `max_quality` or more
+    pub max_mismatch: usize,
+    pub max_quality: u8,
+    /// Penalty of an inserted or deleted base
+    pub gap: usize,
+    /// Penalty of aligning a wildcard at a cost
+    pub wildcard: usize,
+}
+
+impl Default for Penalties {
+    fn default() -> Self {
+        Penalties {
+            min_mismatch: 2,
+            max_mismatch: 6,
+            max_quality: 40,
+            gap: 6,
+            wildcard: 1,
+        }
+    }
+}
+
+impl Penalties {
+    /// Penalty of a mismatch at a base of Phred quality `quality`, scaled
+    /// linearly between `min_mismatch` and `max_mismatch`.
+    pub fn mismatch(&self, quality: u8) -> usize {
+        let quality = quality.min(self.max_quality) as usize;
+        let spread = self.max_mismatch.saturating_sub(self.min_mismatch);
+        self.min_mismatch + spread * quality / (self.max_quality as usize).max(1)
+    }
+
+    /// The least penalty of an edit, per unit of edit cost. Wildcards that cost
+    /// `wildcard_cost` edits may cost less than one edit each.
+    pub fn min_per_edit(&self, wildcard_cost: usize) -> usize {
+        let wildcard = match wildcard_cost {
+            0 => usize::MAX,
+            cost => self.wildcard / cost,
+        };
+        self.min_mismatch.min(self.gap).min(wildcard)
+    }
+}
+
+/// Quality-aware scoring of the alignments of one read.
+#[derive(Copy, Clone, Debug)]
+pub struct QualityScoring<'a> {
+    /// Phred qualities of the query bases, in the orientation of the query
+    pub qualities: &'a [u8],
+    pub penalties: Penalties,
+    /// Alignments with a higher penalty are pruned during the search
+    pub max_penalty: Option<usize>,
+}
+
+impl QualityScoring<'_> {
+    /// Penalty of an operation aligning the query symbol `query_char` at position
+    /// `i`. Bases without a quality are taken to be of the highest quality.
+    pub fn penalty(&self, op: CigarOp, query_char: u8, i: usize) -> usize {
+        match op {
+            CigarOp::Match | CigarOp::SoftClip => 0,
+            CigarOp::Mismatch if query_char == WILDCARD => self.penalties.wildcard,
+            CigarOp::Mismatch => {
+                let quality = self.qualities.get(i).copied();
+                self.penalties
+                    .mismatch(quality.unwrap_or(self.penalties.max_quality))
+            }
+            CigarOp::Insertion | CigarOp::Deletion => self.penalties.gap,
+        }
+    }
+
+    /// Penalty of aligning the query along a CIGAR.
+    pub fn alignment_penalty(&self, query: &[u8], cigar: &Cigar) -> usize {
+        let mut i = 0;
+        let mut penalty = 0;
+        for op in cigar.ops() {
+            let query_char = query.get(i).copied().unwrap_or(0);
+            penalty += self.penalty(op, query_char, i);
+            i += op.consumes_query() as usize;
+        }
+        penalty
+    }
+
+    /// Whether a search branch with `penalty` so far, and at least `edits_left`
+    /// units of edit cost still to come, can stay within `max_penalty`.
+    pub(crate) fn allows(&self, penalty: usize, edits_left: usize, wildcard_cost: usize) -> bool {
+        match self.max_penalty {
+            Some(max_penalty) => {
+                let least = edits_left.saturating_mul(self.penalties.min_per_edit(wildcard_cost));
+                penalty.saturating_add(least) <= max_penalty
+            }
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::remap_query;
+
+    #[test]
+    fn test_phred_qualities() {
+        assert_eq!(phred_qualities("!+5I"), vec![0, 10, 20, 40]);
+    }
+
+    #[test]
+    fn test_mismatch_penalties() {
+        let penalties = Penalties::default();
+        assert_eq!(penalties.mismatch(0), 2);
+        assert_eq!(penalties.mismatch(20), 4);
+        assert_eq!(penalties.mismatch(40), 6);
+        assert_eq!(penalties.mismatch(60), 6);
+        assert_eq!(penalties.min_per_edit(1), 1);
+        assert_eq!(penalties.min_per_edit(0), 2);
+    }
+
+    #[test]
+    fn test_alignment_penalty() {
+        let qualities = phred_qualities("I#I5I");
+        let scoring = QualityScoring {
+            qualities: &qualities,
+            penalties: Penalties::default(),
+            max_penalty: None,
+        };
+        let query = remap_query("ACNTA").unwrap();
+        let penalty = |cigar: &str| scoring.alignment_penalty(&query, &cigar.parse().unwrap());
+
+        assert_eq!(penalty("5="), 0);
+        // A mismatch at the low quality base is cheaper than at a high quality one
+        assert_eq!(penalty("1=1X3="), 2);
+        assert_eq!(penalty("1X4="), 6);
+        // The wildcard, and the base after a deletion
+        assert_eq!(penalty("2=1X1D1X1="), 1 + 6 + 4);
+        assert_eq!(penalty("1I4="), 6);
+    }
+}
@@ -0,0 +1,21 @@
+/// Rank queries over the Burrows-Wheeler transform of a string, which is all
+/// backward search needs. Implemented by the sampled `OTable` and by
+/// `WaveletTree`, so their space and time can be compared on the same searches.
+pub trait Rank {
+    /// Counts the occurrences of `a` before position `i` of the BWT, for `i` up
+    /// to and including `len()`.
+    fn rank(&self, a: u8, i: usize) -> usize;
+
+    /// The symbol at position `i` of the BWT.
+    fn symbol(&self, i: usize) -> u8;
+
+    /// The length of the BWT.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Memory used by the structure.
+    fn size_in_bytes(&self) -> usize;
+}
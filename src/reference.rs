@@ -0,0 +1,266 @@
+use crate::error::{with_path, GeneSearchError, Result};
+use crate::input::{input_stem, open_input};
+use crate::storage::Storage;
+use crate::util::is_known_symbol;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+/// Placed between records when they are concatenated. Searches only ever extend
+/// over A, C, G and T, so no match can span two records. It remaps to the same
+/// `$` as the sentinel.
+pub const RECORD_SEPARATOR: char = '$';
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReferenceRecord {
+    pub name: String,
+    /// Offset of the first base of the record in the concatenated sequence
+    pub offset: usize,
+    pub len: usize,
+}
+
+/// A reference genome made up of one or more FASTA records.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    /// All records concatenated, separated by `RECORD_SEPARATOR`. An opened index
+    /// maps it from the file, so it is only read if it is used.
+    pub sequence: Storage<u8>,
+    pub records: Vec<ReferenceRecord>,
+}
+
+impl Reference {
+    /// Parses every record of a FASTA file. Sequence lines before the first header
+    /// are kept as a record named `default_name`. Fails on characters that are not
+    /// bases or IUPAC ambiguity codes, `RECORD_SEPARATOR` included.
+    pub fn from_fasta<R: BufRead>(reader: R, default_name: &str) -> Result<Self> {
+        let mut sequence = Vec::new();
+        let mut records = Vec::new();
+
+        for (line_number, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim_end();
+
+            if let Some(header) = line.strip_prefix('>') {
+                let name = header.split_whitespace().next().unwrap_or("");
+                start_record(&mut sequence, &mut records, name);
+            } else if !line.is_empty() {
+                if let Some(c) = line.chars().find(|&c| !is_known_symbol(c)) {
+                    return Err(GeneSearchError::Parse(format!(
+                        "unexpected character {:?} on line {}",
+                        c,
+                        line_number + 1
+                    )));
+                }
+                if records.is_empty() {
+                    start_record(&mut sequence, &mut records, default_name);
+                }
+                sequence.extend_from_slice(line.as_bytes());
+                records.last_mut().unwrap().len += line.len();
+            }
+        }
+
+        Ok(Reference {
+            sequence: sequence.into(),
+            records,
+        })
+    }
+
+    /// Reads the genome `file_name` from `resources/genomes/`.
+    pub fn from_file(file_name: &str) -> Result<Self> {
+        let mut path = Path::new("resources/genomes/").join(file_name);
+        path.set_extension("fa");
+        Reference::from_path(&path)
+    }
+
+    /// Reads a FASTA file at any path, which may be gzip-compressed. A headerless
+    /// file gets the file stem as its name.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let input = open_input(path)?;
+        let result = Reference::from_fasta(BufReader::new(input), &input_stem(path));
+        with_path(result, path)
+    }
+
+    /// The concatenated sequence as text. `from_fasta` only accepts ASCII, but a
+    /// sequence read from an index file is checked here, when it is first needed.
+    pub fn text(&self) -> Result<&str> {
+        std::str::from_utf8(&self.sequence)
+            .map_err(|_| GeneSearchError::Parse("reference is not UTF-8".to_string()))
+    }
+
+    /// Translates an offset into the concatenated sequence into the name of the
+    /// record it falls in and the 0-based position within that record.
+    /// Returns `None` for offsets on a separator or past the end.
+    pub fn resolve(&self, position: usize) -> Option<(&str, usize)> {
+        let index = match self
+            .records
+            .binary_search_by(|record| record.offset.cmp(&position))
+        {
+            Ok(index) => index,
+            Err(0) => return None,
+            Err(index) => index - 1,
+        };
+
+        let record = &self.records[index];
+        if position < record.offset + record.len {
+            Some((&record.name, position - record.offset))
+        } else {
+            None
+        }
+    }
+
+    /// Resolves located offsets, skipping any that do not fall inside a record.
+    pub fn resolve_all(&self, positions: &[usize]) -> Vec<(&str, usize)> {
+        positions.iter().filter_map(|&p| self.resolve(p)).collect()
+    }
+}
+
+fn start_record(sequence: &mut Vec<u8>, records: &mut Vec<ReferenceRecord>, name: &str) {
+    if !records.is_empty() {
+        sequence.push(RECORD_SEPARATOR as u8);
+    }
+    records.push(ReferenceRecord {
+        name: name.to_string(),
+        offset: sequence.len(),
+        len: 0,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        exact_search::bwt_search,
+        locate::locate_exact,
+        o_table::OTable,
+        sais::suffix_array_induced_sort,
+        sampled_suffix_array::{SampledSuffixArray, SamplingStrategy},
+        table_gen::generate_c_table,
+        util::remap_query,
+        util::remap_reference,
+    };
+    use std::io::Cursor;
+
+    const MULTI_FASTA: &str = ">chr1 first record\nACGT\nAC\n>chr2\nTTGA\n\n>chrM\nGTTT\n";
+
+    #[test]
+    fn test_parse_records() {
+        let reference = Reference::from_fasta(Cursor::new(MULTI_FASTA), "unnamed").unwrap();
+
+        assert_eq!(reference.text().unwrap(), "ACGTAC$TTGA$GTTT");
+        let names: Vec<&str> = reference.records.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["chr1", "chr2", "chrM"]);
+        assert_eq!(reference.records[1].offset, 7);
+        assert_eq!(reference.records[1].len, 4);
+    }
+
+    #[test]
+    fn test_parse_headerless() {
+        let reference = Reference::from_fasta(Cursor::new("ACGT\nTT\n"), "rand").unwrap();
+
+        assert_eq!(reference.text().unwrap(), "ACGTTT");
+        assert_eq!(reference.records.len(), 1);
+        assert_eq!(reference.records[0].name, "rand");
+    }
+
+    #[test]
+    fn test_parse_header_with_leading_space() {
+        let reference = Reference::from_file("hg38-1000").unwrap();
+
+        assert_eq!(reference.records.len(), 1);
+        assert_eq!(reference.records[0].name, "chr1");
+        assert!(!reference.sequence.contains(&b'>'));
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_characters() {
+        let result = Reference::from_fasta(Cursor::new(">chr1\nACGT\nAC-GT\n"), "unnamed");
+
+        match result {
+            Err(GeneSearchError::Parse(message)) => assert!(message.contains("line 3")),
+            _ => panic!("expected a parse error"),
+        }
+
+        // The separator would be taken for a record boundary
+        let result = Reference::from_fasta(
+            Cursor::new(
+                ">chr1
+AC$GT
+",
+            ),
+            "unnamed",
+        );
+        assert!(matches!(result, Err(GeneSearchError::Parse(_))));
+    }
+
+    #[test]
+    fn test_from_gzip_path() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+        use std::io::Write;
+
+        let path = std::env::temp_dir().join("gene_search_test_chr9.fa.gz");
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"ACGT\nNNAC\n").unwrap();
+        std::fs::write(&path, encoder.finish().unwrap()).unwrap();
+        let reference = Reference::from_path(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let reference = reference.unwrap();
+        assert_eq!(reference.text().unwrap(), "ACGTNNAC");
+        assert_eq!(reference.records[0].name, "gene_search_test_chr9");
+    }
+
+    #[test]
+    fn test_resolve() {
+        let reference = Reference::from_fasta(Cursor::new(MULTI_FASTA), "unnamed").unwrap();
+
+        assert_eq!(reference.resolve(0), Some(("chr1", 0)));
+        assert_eq!(reference.resolve(5), Some(("chr1", 5)));
+        assert_eq!(reference.resolve(6), None);
+        assert_eq!(reference.resolve(7), Some(("chr2", 0)));
+        assert_eq!(reference.resolve(13), Some(("chrM", 1)));
+        assert_eq!(reference.resolve(16), None);
+    }
+
+    #[test]
+    fn test_match_cannot_span_records() {
+        let reference = Reference::from_fasta(Cursor::new(MULTI_FASTA), "unnamed").unwrap();
+        let remapped = remap_reference(reference.text().unwrap()).unwrap();
+        let suffix_array = suffix_array_induced_sort(&remapped);
+        let o_table = OTable::new(&remapped, &suffix_array, 10);
+        let c_table = generate_c_table(&remapped);
+
+        let spanning = bwt_search(&remap_query("GAGT").unwrap(), &o_table, &c_table);
+        assert!(locate_exact(&suffix_array, spanning, None).is_empty());
+
+        let within = bwt_search(&remap_query("TTG").unwrap(), &o_table, &c_table);
+        let positions = locate_exact(&suffix_array, within, None);
+        assert_eq!(reference.resolve_all(&positions), vec![("chr2", 0)]);
+    }
+
+    #[test]
+    fn test_locate_records_with_sampled_suffix_array() {
+        let reference = Reference::from_fasta(Cursor::new(MULTI_FASTA), "unnamed").unwrap();
+        let remapped = remap_reference(reference.text().unwrap()).unwrap();
+        let suffix_array = suffix_array_induced_sort(&remapped);
+        let o_table = OTable::new(&remapped, &suffix_array, 10);
+        let c_table = generate_c_table(&remapped);
+
+        for &strategy in &[SamplingStrategy::TextPosition, SamplingStrategy::Rank] {
+            for rate in 1..8 {
+                let sampled = SampledSuffixArray::new(&remapped, &suffix_array, rate, strategy);
+                let lookup = sampled.with_tables(&o_table, &c_table);
+                for (query, expected) in [
+                    ("TTG", vec![("chr2", 0)]),
+                    ("GT", vec![("chr1", 2), ("chrM", 0)]),
+                    ("AC", vec![("chr1", 0), ("chr1", 4)]),
+                ] {
+                    let interval = bwt_search(&remap_query(query).unwrap(), &o_table, &c_table);
+                    let mut positions = locate_exact(&lookup, interval, None);
+                    assert_eq!(positions, locate_exact(&suffix_array, interval, None));
+                    positions.sort_unstable();
+                    assert_eq!(reference.resolve_all(&positions), expected, "{}", query);
+                }
+            }
+        }
+    }
+}
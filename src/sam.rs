@@ -0,0 +1,414 @@
+use crate::hits::{hits_from_approx, hits_from_exact, Hit, ReportPolicy};
+use crate::locate::SuffixLookup;
+use crate::quality::QualityScoring;
+use crate::reference::Reference;
+use crate::types::StrandAlignments;
+use crate::util::{reverse_complement_text, ReadRecord, Strand};
+use std::io::{Result, Write};
+
+pub const FLAG_UNMAPPED: u16 = 0x4;
+pub const FLAG_REVERSE: u16 = 0x10;
+pub const FLAG_SECONDARY: u16 = 0x100;
+
+/// One alignment line of a SAM file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SamRecord {
+    pub qname: String,
+    pub flag: u16,
+    pub rname: String,
+    /// 1-based leftmost reference position, 0 when unmapped
+    pub pos: usize,
+    pub mapq: u8,
+    pub cigar: String,
+    pub seq: String,
+    pub qual: String,
+    /// Edit distance to the reference, written as the NM tag
+    pub edits: Option<usize>,
+    /// Alignment score, written as the AS tag
+    pub score: Option<i64>,
+}
+
+impl SamRecord {
+    pub fn unmapped(read: &ReadRecord) -> Self {
+        SamRecord {
+            qname: read.name.clone(),
+            flag: FLAG_UNMAPPED,
+            rname: "*".to_string(),
+            pos: 0,
+            mapq: 0,
+            cigar: "*".to_string(),
+            seq: read.sequence.clone(),
+            qual: read.quality.clone(),
+            edits: None,
+            score: None,
+        }
+    }
+}
+
+pub struct SamWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> SamWriter<W> {
+    pub fn new(writer: W) -> Self {
+        SamWriter { writer }
+    }
+
+    /// Writes the header with one @SQ line per reference record.
+    pub fn write_header(&mut self, reference: &Reference) -> Result<()> {
+        writeln!(self.writer, "@HD\tVN:1.6\tSO:unsorted")?;
+        for record in &reference.records {
+            writeln!(self.writer, "@SQ\tSN:{}\tLN:{}", record.name, record.len)?;
+        }
+        writeln!(
+            self.writer,
+            "@PG\tID:gene_search\tPN:gene_search\tVN:{}",
+            env!("CARGO_PKG_VERSION")
+        )
+    }
+
+    pub fn write_record(&mut self, record: &SamRecord) -> Result<()> {
+        write!(
+            self.writer,
+            "{}\t{}\t{}\t{}\t{}\t{}\t*\t0\t0\t{}\t{}",
+            record.qname,
+            record.flag,
+            record.rname,
+            record.pos,
+            record.mapq,
+            record.cigar,
+            or_star(&record.seq),
+            or_star(&record.qual),
+        )?;
+        if let Some(edits) = record.edits {
+            write!(self.writer, "\tNM:i:{}", edits)?;
+        }
+        if let Some(score) = record.score {
+            write!(self.writer, "\tAS:i:{}", score)?;
+        }
+        writeln!(self.writer)
+    }
+
+    pub fn write_records(&mut self, records: &[SamRecord]) -> Result<()> {
+        for record in records {
+            self.write_record(record)?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()
+    }
+}
+
+fn or_star(s: &str) -> &str {
+    if s.is_empty() {
+        "*"
+    } else {
+        s
+    }
+}
+
+/// Phred-scaled probability that a hit is wrong, when it is picked among `hits`
+/// equally good hits. Capped at 60.
+pub fn mapping_quality(hits: usize) -> u8 {
+    if hits <= 1 {
+        return 60;
+    }
+    let wrong = 1.0 - 1.0 / hits as f64;
+    (-10.0 * wrong.log10()).min(60.0) as u8
+}
+
+/// Turns the collapsed hits of a read into SAM records, keeping those `policy`
+/// reports. The best hit is primary and the rest are secondary, and the mapping
+/// quality counts every hit with the lowest penalty, reported or not. Unmapped
+/// reads get a single record. Reverse strand hits get the reverse complemented
+/// read and reversed qualities.
+fn records_from_hits(
+    read: &ReadRecord,
+    mut hits: Vec<Hit>,
+    reference: &Reference,
+    policy: ReportPolicy,
+) -> Vec<SamRecord> {
+    // An empty read matches at every position with an empty CIGAR, which a mapped
+    // SAM record cannot have, so such hits leave the read unmapped
+    hits.retain(|hit| !hit.cigar.is_empty());
+    let best_penalty = match hits.first() {
+        Some(hit) => hit.penalty,
+        None => return vec![SamRecord::unmapped(read)],
+    };
+    let best_hits = hits
+        .iter()
+        .filter(|hit| hit.penalty == best_penalty)
+        .count();
+    let mapq = mapping_quality(best_hits);
+    policy.apply(&mut hits);
+
+    hits.iter()
+        .filter_map(|hit| {
+            let (name, offset) = reference.resolve(hit.position)?;
+            Some((hit, name, offset))
+        })
+        .enumerate()
+        .map(|(i, (hit, name, offset))| {
+            let (flag, seq, qual) = match (i, hit.strand) {
+                (0, Strand::Forward) => (0, read.sequence.clone(), read.quality.clone()),
+                (0, Strand::Reverse) => (
+                    FLAG_REVERSE,
+                    reverse_complement_text(&read.sequence),
+                    read.quality.chars().rev().collect(),
+                ),
+                (_, Strand::Forward) => (FLAG_SECONDARY, String::new(), String::new()),
+                (_, Strand::Reverse) => {
+                    (FLAG_SECONDARY | FLAG_REVERSE, String::new(), String::new())
+                }
+            };
+            SamRecord {
+                qname: read.name.clone(),
+                flag,
+                rname: name.to_string(),
+                pos: offset + 1,
+                mapq: if i == 0 { mapq } else { 0 },
+                cigar: hit.cigar.to_sam(),
+                seq,
+                qual,
+                edits: Some(hit.edits),
+                score: Some(-(hit.penalty as i64)),
+            }
+        })
+        .collect()
+}
+
+/// SAM records for the strand-tagged inclusive intervals returned by `bwt_search_strands`.
+pub fn records_from_exact<S: SuffixLookup + ?Sized>(
+    read: &ReadRecord,
+    intervals: &[(Strand, (usize, usize))],
+    suffix_array: &S,
+    reference: &Reference,
+    policy: ReportPolicy,
+) -> Vec<SamRecord> {
+    let hits = hits_from_exact(intervals, read.sequence.len(), suffix_array);
+    records_from_hits(read, hits, reference, policy)
+}
+
+/// SAM records for the results of `approx_search_strands` for `query`, the
+/// remapped sequence of the read, scored with the scoring it was searched with,
+/// if any.
+pub fn records_from_approx<S: SuffixLookup + ?Sized>(
+    read: &ReadRecord,
+    query: &[u8],
+    results: &StrandAlignments,
+    suffix_array: &S,
+    reference: &Reference,
+    scoring: Option<&QualityScoring>,
+    policy: ReportPolicy,
+) -> Vec<SamRecord> {
+    let hits = hits_from_approx(results, suffix_array, query, scoring);
+    records_from_hits(read, hits, reference, policy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        approx_search::{approx_search_strands, ApproxSearchParams, EditModel},
+        exact_search::bwt_search_strands,
+        o_table::OTable,
+        suffix_array_construction::construct_suffix_array_naive,
+        table_gen::generate_c_table,
+        util::{remap_query, remap_reference},
+    };
+    use std::collections::HashSet;
+    use std::io::Cursor;
+
+    fn read(name: &str, sequence: &str) -> ReadRecord {
+        ReadRecord {
+            name: name.to_string(),
+            sequence: sequence.to_string(),
+            quality: "I".repeat(sequence.len()),
+        }
+    }
+
+    #[test]
+    fn test_mapping_quality() {
+        assert_eq!(mapping_quality(1), 60);
+        assert_eq!(mapping_quality(2), 3);
+        assert_eq!(mapping_quality(10), 0);
+    }
+
+    #[test]
+    fn test_write_header() {
+        let fasta = ">chr1\nACGT\n>chr2 desc\nTTGAC\n";
+        let reference = Reference::from_fasta(Cursor::new(fasta), "unnamed").unwrap();
+        let mut out = Vec::new();
+        SamWriter::new(&mut out).write_header(&reference).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+
+        assert_eq!(lines[0], "@HD\tVN:1.6\tSO:unsorted");
+        assert_eq!(lines[1], "@SQ\tSN:chr1\tLN:4");
+        assert_eq!(lines[2], "@SQ\tSN:chr2\tLN:5");
+        assert!(lines[3].starts_with("@PG\tID:gene_search"));
+    }
+
+    #[test]
+    fn test_exact_records() {
+        let fasta = ">chr1\nAGAGA\n>chr2\nCAGAT\n";
+        let reference = Reference::from_fasta(Cursor::new(fasta), "unnamed").unwrap();
+        let remapped = remap_reference(reference.text().unwrap()).unwrap();
+        let suffix_array = construct_suffix_array_naive(&remapped);
+        let o_table = OTable::new(&remapped, &suffix_array, 10);
+        let c_table = generate_c_table(&remapped);
+        let intervals = bwt_search_strands(&remap_query("AGA").unwrap(), &o_table, &c_table, false);
+
+        let records = records_from_exact(
+            &read("r1", "AGA"),
+            &intervals,
+            &suffix_array,
+            &reference,
+            ReportPolicy::AllBest,
+        );
+        let mut out = Vec::new();
+        SamWriter::new(&mut out).write_records(&records).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[0],
+            "r1\t0\tchr1\t1\t1\t3M\t*\t0\t0\tAGA\tIII\tNM:i:0\tAS:i:0"
+        );
+        assert_eq!(
+            lines[1],
+            "r1\t256\tchr1\t3\t0\t3M\t*\t0\t0\t*\t*\tNM:i:0\tAS:i:0"
+        );
+        assert_eq!(
+            lines[2],
+            "r1\t256\tchr2\t2\t0\t3M\t*\t0\t0\t*\t*\tNM:i:0\tAS:i:0"
+        );
+
+        // The mapping quality still counts the hits that are not reported
+        let records = records_from_exact(
+            &read("r1", "AGA"),
+            &intervals,
+            &suffix_array,
+            &reference,
+            ReportPolicy::BestOnly,
+        );
+        assert_eq!(records.len(), 1);
+        assert_eq!((records[0].pos, records[0].mapq), (1, 1));
+    }
+
+    #[test]
+    fn test_empty_read_is_unmapped() {
+        let fasta = ">chr1\nAGAGA\n";
+        let reference = Reference::from_fasta(Cursor::new(fasta), "unnamed").unwrap();
+        let remapped = remap_reference(reference.text().unwrap()).unwrap();
+        let suffix_array = construct_suffix_array_naive(&remapped);
+        let o_table = OTable::new(&remapped, &suffix_array, 10);
+        let c_table = generate_c_table(&remapped);
+        let intervals = bwt_search_strands(&[], &o_table, &c_table, true);
+
+        let records = records_from_exact(
+            &read("empty", ""),
+            &intervals,
+            &suffix_array,
+            &reference,
+            ReportPolicy::AllWithin(0),
+        );
+        assert_eq!(records, vec![SamRecord::unmapped(&read("empty", ""))]);
+    }
+
+    #[test]
+    fn test_approx_records() {
+        let fasta = ">chr1\nTACGT\n";
+        let reference = Reference::from_fasta(Cursor::new(fasta), "unnamed").unwrap();
+        let remapped = remap_reference(reference.text().unwrap()).unwrap();
+        let suffix_array = construct_suffix_array_naive(&remapped);
+        let reverse_remapped: Vec<u8> = remapped.iter().rev().copied().collect();
+        let reverse_suffix_array = construct_suffix_array_naive(&reverse_remapped);
+
+        let params = ApproxSearchParams {
+            reference: &remapped,
+            query: &remap_query("TAGT").unwrap(),
+            o_table: &OTable::new(&remapped, &suffix_array, 10),
+            c_table: &generate_c_table(&remapped),
+            rev_o_table: &OTable::new(&reverse_remapped, &reverse_suffix_array, 10),
+            edits: 1,
+            edit_model: EditModel::Levenshtein,
+            wildcard_cost: 1,
+            scoring: None,
+        };
+        let results = approx_search_strands(params, true);
+
+        let records = records_from_approx(
+            &read("r2", "TAGT"),
+            params.query,
+            &results,
+            &suffix_array,
+            &reference,
+            None,
+            ReportPolicy::AllWithin(1),
+        );
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].rname, "chr1");
+        assert_eq!(records[0].pos, 1);
+        assert_eq!(records[0].mapq, 60);
+        assert_eq!(records[0].cigar, "2M1D2M");
+        assert_eq!(records[0].edits, Some(1));
+        assert_eq!(records[0].score, Some(-1));
+    }
+
+    #[test]
+    fn test_reverse_record() {
+        let fasta = ">chr1\nGGCATTGACC\n";
+        let reference = Reference::from_fasta(Cursor::new(fasta), "unnamed").unwrap();
+        let remapped = remap_reference(reference.text().unwrap()).unwrap();
+        let suffix_array = construct_suffix_array_naive(&remapped);
+        let o_table = OTable::new(&remapped, &suffix_array, 10);
+        let c_table = generate_c_table(&remapped);
+        let intervals =
+            bwt_search_strands(&remap_query("CAATG").unwrap(), &o_table, &c_table, true);
+
+        let read = ReadRecord {
+            name: "r4".to_string(),
+            sequence: "CAATG".to_string(),
+            quality: "ABCDE".to_string(),
+        };
+        let records = records_from_exact(
+            &read,
+            &intervals,
+            &suffix_array,
+            &reference,
+            ReportPolicy::AllBest,
+        );
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].flag, FLAG_REVERSE);
+        assert_eq!(records[0].pos, 3);
+        assert_eq!(records[0].seq, "CATTG");
+        assert_eq!(records[0].qual, "EDCBA");
+    }
+
+    #[test]
+    fn test_unmapped_record() {
+        let results = HashSet::new();
+        let reference = Reference::from_fasta(Cursor::new(">chr1\nACGT\n"), "unnamed").unwrap();
+        let records = records_from_approx(
+            &read("r3", "GGG"),
+            &remap_query("GGG").unwrap(),
+            &results,
+            &vec![0usize],
+            &reference,
+            None,
+            ReportPolicy::AllBest,
+        );
+        let mut out = Vec::new();
+        SamWriter::new(&mut out).write_records(&records).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "r3\t4\t*\t0\t0\t*\t*\t0\t0\tGGG\tIII\n"
+        );
+    }
+}
@@ -0,0 +1,309 @@
+use crate::locate::SuffixLookup;
+use crate::o_table::OTable;
+
+/// Decides which suffix array entries a `SampledSuffixArray` keeps.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SamplingStrategy {
+    /// Keep the entries whose text position is a multiple of the rate.
+    /// Every lookup takes at most `rate - 1` LF steps.
+    TextPosition,
+    /// Keep the entries whose rank is a multiple of the rate.
+    /// Needs no bit vector, but lookups have no upper bound on LF steps.
+    Rank,
+}
+
+/// A suffix array that only keeps every k'th entry. The remaining entries are
+/// recovered by walking LF-mapping steps through an O-table and C-table.
+///
+/// Record separators and Ns are `$` too, so the text holds `$` in more places
+/// than its end. LF-mapping over an inner `$` works like over any base, as the
+/// suffixes after the `$`s are sorted like the `$`s themselves. Only the final
+/// `$` is out of place: the suffix made of it alone sorts first, wherever the
+/// first suffix of the text sorts. Its row and rank are kept to correct for it,
+/// so a text full of Ns costs no more entries than any other.
+///
+/// `FmIndex` does not use it yet: it keeps, saves and maps the full suffix array.
+pub struct SampledSuffixArray {
+    samples: Vec<usize>,
+    rate: usize,
+    strategy: SamplingStrategy,
+    len: usize,
+    // Bit per rank marking sampled rows, only used with text position sampling
+    marks: Vec<u64>,
+    // Number of marked rows before each word in `marks`
+    mark_ranks: Vec<usize>,
+    // The row of the first suffix, whose BWT symbol is the final `$`, and the
+    // number of rows with a `$` in the BWT before it
+    first_suffix_row: usize,
+    first_suffix_rank: usize,
+}
+
+impl SampledSuffixArray {
+    /// Samples the suffix array of a remapped text, keeping roughly one in every
+    /// `rate` entries.
+    pub fn new(
+        text: &[u8],
+        suffix_array: &[usize],
+        rate: usize,
+        strategy: SamplingStrategy,
+    ) -> Self {
+        let len = suffix_array.len();
+        let mut samples = Vec::with_capacity(len / rate + 1);
+        let mut marks = vec![];
+        let mut mark_ranks = vec![];
+
+        match strategy {
+            SamplingStrategy::TextPosition => {
+                marks = vec![0u64; len / 64 + 1];
+                for (i, &suffix) in suffix_array.iter().enumerate() {
+                    if suffix.is_multiple_of(rate) {
+                        marks[i / 64] |= 1 << (i % 64);
+                        samples.push(suffix);
+                    }
+                }
+
+                let mut count = 0;
+                for word in &marks {
+                    mark_ranks.push(count);
+                    count += word.count_ones() as usize;
+                }
+            }
+            SamplingStrategy::Rank => {
+                samples.extend(suffix_array.iter().step_by(rate));
+            }
+        }
+
+        let first_suffix_row = suffix_array.iter().position(|&suffix| suffix == 0).unwrap();
+        let first_suffix_rank = suffix_array[..first_suffix_row]
+            .iter()
+            .filter(|&&suffix| suffix > 0 && text[suffix - 1] == 0)
+            .count();
+
+        SampledSuffixArray {
+            samples,
+            rate,
+            strategy,
+            len,
+            marks,
+            mark_ranks,
+            first_suffix_row,
+            first_suffix_rank,
+        }
+    }
+
+    /// Number of entries in the full suffix array.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of stored suffix array entries.
+    pub fn sample_count(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Returns the stored entry for rank i, if rank i was sampled.
+    fn sample_at(&self, i: usize) -> Option<usize> {
+        match self.strategy {
+            SamplingStrategy::TextPosition => {
+                let (word, bit) = (i / 64, i % 64);
+                if self.marks[word] & (1 << bit) == 0 {
+                    return None;
+                }
+                let below = self.marks[word] & ((1 << bit) - 1);
+                Some(self.samples[self.mark_ranks[word] + below.count_ones() as usize])
+            }
+            SamplingStrategy::Rank => {
+                if i.is_multiple_of(self.rate) {
+                    Some(self.samples[i / self.rate])
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// Recovers the i'th suffix array entry. Walks backwards through the text with
+    /// LF-mapping until a sampled row is found, and adds the number of steps taken.
+    ///
+    /// Panics if the walk takes more steps than the text is long, which means the
+    /// tables do not belong to the suffix array that was sampled.
+    pub fn get(&self, i: usize, o_table: &OTable, c_table: &[usize]) -> usize {
+        let start = i;
+        let mut i = i;
+        let mut steps = 0;
+
+        loop {
+            if let Some(suffix) = self.sample_at(i) {
+                return (suffix + steps) % self.len;
+            }
+
+            let a = o_table.bwt_symbol(i);
+            i = if a != 0 {
+                c_table[a as usize] + o_table.get(a, i)
+            } else if i == self.first_suffix_row {
+                // The suffix of just the final `$`
+                0
+            } else {
+                // Every other `$` row comes after that one, in the order of the
+                // rows they precede
+                let rank = o_table.get(0, i);
+                rank + usize::from(rank < self.first_suffix_rank)
+            };
+            steps += 1;
+            assert!(
+                steps < self.len,
+                "LF walk from rank {} found no sample, the tables do not match the suffix array",
+                start
+            );
+        }
+    }
+
+    /// Bundles the sampled suffix array with the tables needed to walk it, so it
+    /// can be passed to the locate functions in place of a full suffix array.
+    pub fn with_tables<'a>(
+        &'a self,
+        o_table: &'a OTable,
+        c_table: &'a [usize],
+    ) -> SampledLookup<'a> {
+        SampledLookup {
+            sampled: self,
+            o_table,
+            c_table,
+        }
+    }
+}
+
+pub struct SampledLookup<'a> {
+    sampled: &'a SampledSuffixArray,
+    o_table: &'a OTable,
+    c_table: &'a [usize],
+}
+
+impl<'a> SuffixLookup for SampledLookup<'a> {
+    fn suffix(&self, i: usize) -> usize {
+        self.sampled.get(i, self.o_table, self.c_table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        locate::locate_exact, sais::suffix_array_induced_sort,
+        suffix_array_construction::construct_suffix_array_naive, table_gen::generate_c_table,
+        util::read_and_remap_genome, util::remap_query, util::remap_reference,
+    };
+
+    fn assert_agrees(reference: &[u8], rate: usize, strategy: SamplingStrategy, step: usize) {
+        let suffix_array = suffix_array_induced_sort(reference);
+        let o_table = OTable::new(reference, &suffix_array, 10);
+        let c_table = generate_c_table(reference);
+        let sampled = SampledSuffixArray::new(reference, &suffix_array, rate, strategy);
+
+        for i in (0..suffix_array.len()).step_by(step) {
+            assert_eq!(
+                sampled.get(i, &o_table, &c_table),
+                suffix_array[i],
+                "rank {} with rate {} and {:?}",
+                i,
+                rate,
+                strategy
+            );
+        }
+    }
+
+    #[test]
+    fn test_sampled_sa_small() {
+        let reference = remap_reference("ACGTATCGTGACGGGCTATAGCGATGTCGATGC").unwrap();
+        for &strategy in &[SamplingStrategy::TextPosition, SamplingStrategy::Rank] {
+            for rate in 1..10 {
+                assert_agrees(&reference, rate, strategy, 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sampled_sa_with_inner_sentinels() {
+        // N runs and record separators put `$` inside the text
+        for text in ["TTNAC", "GGNNACGTTAGCNAC", "TTGA$AC", "NNACGT$$GTNCA$T"] {
+            let reference = remap_reference(text).unwrap();
+            assert_eq!(
+                suffix_array_induced_sort(&reference),
+                construct_suffix_array_naive(&reference),
+                "{}",
+                text
+            );
+            for &strategy in &[SamplingStrategy::TextPosition, SamplingStrategy::Rank] {
+                for rate in 1..reference.len() + 2 {
+                    assert_agrees(&reference, rate, strategy, 1);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_sampled_sa_sample_count() {
+        let reference = remap_reference("ACGTATCGTGACGGGCTATAGCGATGTCGATGC").unwrap();
+        let suffix_array = suffix_array_induced_sort(&reference);
+        let sampled = |strategy| SampledSuffixArray::new(&reference, &suffix_array, 4, strategy);
+
+        // 34 entries, sampling positions/ranks 0, 4, ..., 32
+        assert_eq!(sampled(SamplingStrategy::TextPosition).sample_count(), 9);
+        assert_eq!(sampled(SamplingStrategy::Rank).sample_count(), 9);
+
+        // Ns cost no extra entries
+        let reference = remap_reference("ACGTNNNACG").unwrap();
+        let suffix_array = suffix_array_induced_sort(&reference);
+        let sampled = SampledSuffixArray::new(&reference, &suffix_array, 4, SamplingStrategy::Rank);
+        assert_eq!(sampled.sample_count(), 3);
+        let n_rich = remap_reference(&"ACN".repeat(100)).unwrap();
+        let suffix_array = suffix_array_induced_sort(&n_rich);
+        let sampled =
+            SampledSuffixArray::new(&n_rich, &suffix_array, 32, SamplingStrategy::TextPosition);
+        assert_eq!(sampled.sample_count(), 10);
+    }
+
+    #[test]
+    fn test_sampled_sa_hg38_1000() {
+        let reference = read_and_remap_genome("hg38-1000").unwrap();
+        for &strategy in &[SamplingStrategy::TextPosition, SamplingStrategy::Rank] {
+            for &rate in &[1, 4, 16, 32] {
+                assert_agrees(&reference, rate, strategy, 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sampled_sa_hg38_10000() {
+        let reference = read_and_remap_genome("hg38-10000").unwrap();
+        assert_agrees(&reference, 32, SamplingStrategy::TextPosition, 97);
+        assert_agrees(&reference, 32, SamplingStrategy::Rank, 97);
+    }
+
+    #[test]
+    fn test_sampled_sa_rand_200000() {
+        let reference = read_and_remap_genome("rand-200000").unwrap();
+        assert_agrees(&reference, 16, SamplingStrategy::TextPosition, 31);
+    }
+
+    #[test]
+    fn test_sampled_sa_locate() {
+        let reference = remap_reference("AGAGA").unwrap();
+        let suffix_array = suffix_array_induced_sort(&reference);
+        let o_table = OTable::new(&reference, &suffix_array, 10);
+        let c_table = generate_c_table(&reference);
+        let sampled =
+            SampledSuffixArray::new(&reference, &suffix_array, 2, SamplingStrategy::TextPosition);
+        let interval =
+            crate::exact_search::bwt_search(&remap_query("AGA").unwrap(), &o_table, &c_table);
+
+        assert_eq!(
+            locate_exact(&sampled.with_tables(&o_table, &c_table), interval, None),
+            locate_exact(&suffix_array, interval, None)
+        );
+    }
+}
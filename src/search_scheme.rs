@@ -0,0 +1,734 @@
+use crate::approx_search::{
+    approx_search_with_stats, match_cost, reverse_qualities, ApproxSearchParams, EditModel,
+    SearchStats,
+};
+use crate::bidirectional::{BiInterval, BidirectionalIndex};
+use crate::cigar::CigarOp;
+use crate::error::{GeneSearchError, Result};
+use crate::quality::QualityScoring;
+use crate::rank::Rank;
+use crate::types::{Alignments, StrandAlignments};
+use crate::util::{reverse_complement, Strand};
+use crate::ALPHABET;
+use std::collections::HashSet;
+
+/// One search of a scheme: the order to match the pieces of a read in, and the
+/// least and most edits allowed in total once each of them is matched.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Search {
+    pub order: Vec<usize>,
+    pub lower: Vec<usize>,
+    pub upper: Vec<usize>,
+}
+
+impl Search {
+    pub fn new(order: Vec<usize>, lower: Vec<usize>, upper: Vec<usize>) -> Self {
+        Search {
+            order,
+            lower,
+            upper,
+        }
+    }
+
+    /// Whether the search finds the alignments with `edits[i]` edits in piece `i`.
+    pub fn accepts(&self, edits: &[usize]) -> bool {
+        let mut total = 0;
+        for (step, &piece) in self.order.iter().enumerate() {
+            total += edits[piece];
+            if total < self.lower[step] || total > self.upper[step] {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Whether the order visits every piece once, each next to those before it.
+    fn is_connected(&self, pieces: usize) -> bool {
+        let (first, rest) = match self.order.split_first() {
+            Some(split) => split,
+            None => return false,
+        };
+        let (mut lo, mut hi) = (*first, *first);
+        for &piece in rest {
+            if piece + 1 == lo {
+                lo = piece;
+            } else if piece == hi + 1 {
+                hi = piece;
+            } else {
+                return false;
+            }
+        }
+        lo == 0 && hi + 1 == pieces
+    }
+}
+
+/// A set of searches that together find every alignment of a read, split into
+/// pieces, with at most `edits` edits. Each search matches the pieces outwards
+/// from one of them over a bidirectional index, and bounds the edits after every
+/// piece, so pieces matched early are matched with few edits and the branches
+/// are cut where the index is still narrowing fast.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SearchScheme {
+    pieces: usize,
+    edits: usize,
+    searches: Vec<Search>,
+}
+
+impl SearchScheme {
+    /// Checks that every search visits the pieces in a connected order and that
+    /// every way of spreading `edits` edits over the pieces is found by a search.
+    pub fn new(pieces: usize, edits: usize, searches: Vec<Search>) -> Result<Self> {
+        for search in &searches {
+            if !search.is_connected(pieces)
+                || search.lower.len() != pieces
+                || search.upper.len() != pieces
+            {
+                return Err(GeneSearchError::InvalidArgument(format!(
+                    "search {:?} does not visit the {} pieces outwards",
+                    search.order, pieces
+                )));
+            }
+        }
+
+        let scheme = SearchScheme {
+            pieces,
+            edits,
+            searches,
+        };
+        if let Some(missed) = scheme.uncovered() {
+            return Err(GeneSearchError::InvalidArgument(format!(
+                "no search finds alignments with edits {:?} in the pieces",
+                missed
+            )));
+        }
+        Ok(scheme)
+    }
+
+    /// Splits the read into `edits + 1` pieces, one of which must match
+    /// exactly, and searches outwards from each of them.
+    pub fn pigeonhole(edits: usize) -> Self {
+        let pieces = edits + 1;
+        let searches = (0..pieces)
+            .map(|exact| {
+                let order = (exact..pieces).chain((0..exact).rev()).collect();
+                let mut upper = vec![edits; pieces];
+                upper[0] = 0;
+                Search::new(order, vec![0; pieces], upper)
+            })
+            .collect();
+        SearchScheme::new(pieces, edits, searches).expect("pigeonhole schemes cover all edits")
+    }
+
+    /// The schemes of Kucherov et al., which also split the read into `edits + 1`
+    /// pieces but let the later searches skip what the earlier ones found. Only
+    /// known for up to 2 edits.
+    pub fn kucherov(edits: usize) -> Option<Self> {
+        let searches = match edits {
+            0 => vec![Search::new(vec![0], vec![0], vec![0])],
+            1 => vec![
+                Search::new(vec![0, 1], vec![0, 0], vec![0, 1]),
+                Search::new(vec![1, 0], vec![0, 1], vec![0, 1]),
+            ],
+            2 => vec![
+                Search::new(vec![0, 1, 2], vec![0, 0, 0], vec![0, 2, 2]),
+                Search::new(vec![2, 1, 0], vec![0, 0, 0], vec![0, 1, 2]),
+                Search::new(vec![1, 0, 2], vec![0, 0, 1], vec![0, 1, 2]),
+            ],
+            _ => return None,
+        };
+        Some(
+            SearchScheme::new(edits + 1, edits, searches)
+                .expect("Kucherov schemes cover all edits"),
+        )
+    }
+
+    pub fn pieces(&self) -> usize {
+        self.pieces
+    }
+
+    pub fn edits(&self) -> usize {
+        self.edits
+    }
+
+    pub fn searches(&self) -> &[Search] {
+        &self.searches
+    }
+
+    /// A spread of at most `edits` edits over the pieces that no search finds.
+    fn uncovered(&self) -> Option<Vec<usize>> {
+        let mut spread = vec![0; self.pieces];
+        self.first_uncovered(&mut spread, 0, self.edits)
+    }
+
+    fn first_uncovered(
+        &self,
+        spread: &mut Vec<usize>,
+        piece: usize,
+        edits_left: usize,
+    ) -> Option<Vec<usize>> {
+        if piece == self.pieces {
+            return match self.searches.iter().any(|search| search.accepts(spread)) {
+                true => None,
+                false => Some(spread.clone()),
+            };
+        }
+        for edits in 0..=edits_left {
+            spread[piece] = edits;
+            if let Some(missed) = self.first_uncovered(spread, piece + 1, edits_left - edits) {
+                return Some(missed);
+            }
+        }
+        spread[piece] = 0;
+        None
+    }
+
+    /// The start and end of each piece of a read, as equal as possible.
+    fn split(&self, len: usize) -> Vec<(usize, usize)> {
+        let mut start = 0;
+        (0..self.pieces)
+            .map(|piece| {
+                let piece_len = len / self.pieces + usize::from(piece < len % self.pieces);
+                start += piece_len;
+                (start - piece_len, start)
+            })
+            .collect()
+    }
+}
+
+/// Approximate search driven by a search scheme over the forward and reverse
+/// tables of the params. Finds the same alignments as `approx_search`, whose
+/// backtracking it falls back to for reads shorter than the number of pieces.
+///
+/// Panics if the scheme was made for another number of edits than the params.
+pub fn scheme_search<R: Rank + ?Sized>(
+    params: ApproxSearchParams<R>,
+    scheme: &SearchScheme,
+) -> Alignments {
+    scheme_search_with_stats(params, scheme).0
+}
+
+/// Search scheme search, also returning how many nodes of the searches it
+/// visited, counted like in `approx_search_with_stats`. The stack is the deepest
+/// the recursion went.
+pub fn scheme_search_with_stats<R: Rank + ?Sized>(
+    params: ApproxSearchParams<R>,
+    scheme: &SearchScheme,
+) -> (Alignments, SearchStats) {
+    assert_eq!(
+        scheme.edits(),
+        params.edits,
+        "the scheme is made for another number of edits"
+    );
+    if params.query.len() < scheme.pieces() {
+        return approx_search_with_stats(params);
+    }
+
+    let mut search = SchemeSearch {
+        index: BidirectionalIndex::new(params.o_table, params.rev_o_table, params.c_table),
+        query: params.query,
+        pieces: scheme.split(params.query.len()),
+        edit_model: params.edit_model,
+        wildcard_cost: params.wildcard_cost,
+        scoring: params.scoring,
+        left_ops: Vec::new(),
+        right_ops: Vec::new(),
+        results: HashSet::new(),
+        depth: 0,
+        stats: SearchStats::default(),
+    };
+    for s in scheme.searches() {
+        let full = search.index.full();
+        search.match_piece(s, 0, 0, full, 0, 0);
+    }
+    (search.results, search.stats)
+}
+
+/// Search scheme search for the query, and optionally for its reverse complement.
+/// Each result is tagged with the strand it was found on.
+pub fn scheme_search_strands<R: Rank + ?Sized>(
+    params: ApproxSearchParams<R>,
+    scheme: &SearchScheme,
+    both_strands: bool,
+) -> StrandAlignments {
+    let mut results: HashSet<_> = scheme_search(params, scheme)
+        .into_iter()
+        .map(|(left, right, cigar, edits)| (Strand::Forward, left, right, cigar, edits))
+        .collect();
+
+    if both_strands {
+        let reverse = reverse_complement(params.query);
+        let reverse_qualities = reverse_qualities(params.scoring);
+        let reverse_params = ApproxSearchParams {
+            query: &reverse,
+            scoring: params.scoring.map(|scoring| QualityScoring {
+                qualities: &reverse_qualities,
+                ..scoring
+            }),
+            ..params
+        };
+        results.extend(
+            scheme_search(reverse_params, scheme)
+                .into_iter()
+                .map(|(left, right, cigar, edits)| (Strand::Reverse, left, right, cigar, edits)),
+        );
+    }
+    results
+}
+
+/// Which way a piece is matched, away from the pieces matched before it.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Direction {
+    Left,
+    Right,
+}
+
+/// The state of the searches of a scheme for one read.
+///
+/// Every query symbol is matched, substituted or inserted as part of its piece.
+/// Deletions between two pieces belong to the piece on the right, so whichever
+/// way that piece is matched, each alignment has one count of edits per piece.
+struct SchemeSearch<'a, R: ?Sized> {
+    index: BidirectionalIndex<'a, R>,
+    query: &'a [u8],
+    /// Start and end of each piece of the query
+    pieces: Vec<(usize, usize)>,
+    edit_model: EditModel,
+    wildcard_cost: usize,
+    scoring: Option<QualityScoring<'a>>,
+    /// Operations left of the first piece, the leftmost last
+    left_ops: Vec<CigarOp>,
+    /// Operations from the first piece rightwards
+    right_ops: Vec<CigarOp>,
+    results: Alignments,
+    /// Calls of `match_piece` on the current path
+    depth: usize,
+    stats: SearchStats,
+}
+
+impl<R: Rank + ?Sized> SchemeSearch<'_, R> {
+    /// Matches the rest of piece `search.order[step]`, of which `done` symbols
+    /// are matched, with `edits` edits and a quality-aware `penalty` so far.
+    /// Every call is a node of the search.
+    fn match_piece(
+        &mut self,
+        search: &Search,
+        step: usize,
+        done: usize,
+        interval: BiInterval,
+        edits: usize,
+        penalty: usize,
+    ) {
+        self.stats.visited += 1;
+        if !self.within_penalty(penalty) {
+            self.stats.pruned += 1;
+            return;
+        }
+        self.depth += 1;
+        self.stats.max_stack = self.stats.max_stack.max(self.depth);
+        self.extend_piece(search, step, done, interval, edits, penalty);
+        self.depth -= 1;
+    }
+
+    fn extend_piece(
+        &mut self,
+        search: &Search,
+        step: usize,
+        done: usize,
+        interval: BiInterval,
+        edits: usize,
+        penalty: usize,
+    ) {
+        let piece = search.order[step];
+        let (start, end) = self.pieces[piece];
+        let direction = match step {
+            0 => Direction::Left,
+            _ if piece < search.order[0] => Direction::Left,
+            _ => Direction::Right,
+        };
+        let upper = search.upper[step];
+
+        // A piece matched rightwards owns the gap before its first symbol, and one
+        // matched leftwards the gap after its last, unless that is the read's start
+        let gap = match direction {
+            Direction::Right => done < end - start,
+            Direction::Left => done > 0 && (done < end - start || piece > 0),
+        };
+        let indels = self.edit_model == EditModel::Levenshtein;
+        if indels && gap && edits < upper {
+            for c in 1..ALPHABET.len() as u8 {
+                let extended = self.extend(direction, interval, c);
+                if !extended.is_empty() {
+                    let penalty = penalty + self.penalty(CigarOp::Deletion, 0, 0);
+                    self.push(direction, CigarOp::Deletion);
+                    self.match_piece(search, step, done, extended, edits + 1, penalty);
+                    self.pop(direction);
+                }
+            }
+        }
+
+        if done == end - start {
+            if edits >= search.lower[step] {
+                self.next_piece(search, step, interval, edits, penalty);
+            }
+            return;
+        }
+
+        let i = match direction {
+            Direction::Left => end - 1 - done,
+            Direction::Right => start + done,
+        };
+        let symbol = self.query[i];
+        for c in 1..ALPHABET.len() as u8 {
+            let cost = match_cost(c, symbol, self.wildcard_cost) as usize;
+            if edits + cost > upper {
+                continue;
+            }
+            let extended = self.extend(direction, interval, c);
+            if !extended.is_empty() {
+                let op = if cost == 0 {
+                    CigarOp::Match
+                } else {
+                    CigarOp::Mismatch
+                };
+                let penalty = penalty + self.penalty(op, symbol, i);
+                self.push(direction, op);
+                self.match_piece(search, step, done + 1, extended, edits + cost, penalty);
+                self.pop(direction);
+            }
+        }
+
+        if indels && edits < upper {
+            let penalty = penalty + self.penalty(CigarOp::Insertion, symbol, i);
+            self.push(direction, CigarOp::Insertion);
+            self.match_piece(search, step, done + 1, interval, edits + 1, penalty);
+            self.pop(direction);
+        }
+    }
+
+    /// Moves on to the next piece of the search, or records the alignment after
+    /// the last one.
+    fn next_piece(
+        &mut self,
+        search: &Search,
+        step: usize,
+        interval: BiInterval,
+        edits: usize,
+        penalty: usize,
+    ) {
+        if step + 1 < search.order.len() {
+            self.match_piece(search, step + 1, 0, interval, edits, penalty);
+            return;
+        }
+
+        let cigar = self
+            .left_ops
+            .iter()
+            .rev()
+            .chain(&self.right_ops)
+            .copied()
+            .collect();
+        let (left, right) = interval.forward_range();
+        self.results.insert((left, right, cigar, edits));
+    }
+
+    /// Quality-aware penalty of an operation on the query symbol at `i`, 0
+    /// without scoring.
+    fn penalty(&self, op: CigarOp, symbol: u8, i: usize) -> usize {
+        match &self.scoring {
+            Some(scoring) => scoring.penalty(op, symbol, i),
+            None => 0,
+        }
+    }
+
+    fn within_penalty(&self, penalty: usize) -> bool {
+        match &self.scoring {
+            Some(scoring) => scoring.allows(penalty, 0, self.wildcard_cost),
+            None => true,
+        }
+    }
+
+    fn extend(&self, direction: Direction, interval: BiInterval, c: u8) -> BiInterval {
+        match direction {
+            Direction::Left => self.index.extend_left(interval, c),
+            Direction::Right => self.index.extend_right(interval, c),
+        }
+    }
+
+    fn push(&mut self, direction: Direction, op: CigarOp) {
+        match direction {
+            Direction::Left => self.left_ops.push(op),
+            Direction::Right => self.right_ops.push(op),
+        }
+    }
+
+    fn pop(&mut self, direction: Direction) {
+        match direction {
+            Direction::Left => self.left_ops.pop(),
+            Direction::Right => self.right_ops.pop(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alignment::edit_distance;
+    use crate::approx_search::approx_search;
+    use crate::o_table::OTable;
+    use crate::quality::Penalties;
+    use crate::sais::suffix_array_induced_sort;
+    use crate::table_gen::generate_c_table;
+    use crate::util::{remap_query, remap_reference};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_schemes_cover_every_spread_of_edits() {
+        for edits in 0..=4 {
+            let scheme = SearchScheme::pigeonhole(edits);
+            assert_eq!(scheme.pieces(), edits + 1);
+            assert_eq!(scheme.uncovered(), None);
+        }
+        for edits in 0..=2 {
+            assert!(SearchScheme::kucherov(edits).is_some());
+        }
+        assert!(SearchScheme::kucherov(3).is_none());
+
+        // Nothing finds an edit in the first piece
+        let missing = vec![Search::new(vec![0, 1], vec![0, 0], vec![0, 2])];
+        assert!(SearchScheme::new(2, 2, missing).is_err());
+
+        let disconnected = vec![Search::new(vec![0, 2, 1], vec![0; 3], vec![1; 3])];
+        assert!(SearchScheme::new(3, 1, disconnected).is_err());
+    }
+
+    #[test]
+    fn test_split() {
+        let scheme = SearchScheme::pigeonhole(2);
+        assert_eq!(scheme.split(10), vec![(0, 4), (4, 7), (7, 10)]);
+        assert_eq!(scheme.split(3), vec![(0, 1), (1, 2), (2, 3)]);
+    }
+
+    #[test]
+    fn test_same_results_as_backtracking() {
+        let genome = "AGATAGATTCACAGGATTACANNGATTTACAGATTACAGGCATTAGCACGTACG";
+        let reference = remap_reference(genome).unwrap();
+        let mut reverse_reference = reference[..reference.len() - 1].to_vec();
+        reverse_reference.reverse();
+        reverse_reference.push(0);
+        let suffix_array = suffix_array_induced_sort(&reference);
+        let reverse_suffix_array = suffix_array_induced_sort(&reverse_reference);
+        let o_table = OTable::new(&reference, &suffix_array, 10);
+        let rev_o_table = OTable::new(&reverse_reference, &reverse_suffix_array, 10);
+        let c_table = generate_c_table(&reference);
+
+        for read in ["ATT", "GATTACA", "AGATTNACA", "CGTTACGTA", "TTAGCAC"] {
+            let query = remap_query(read).unwrap();
+            let qualities: Vec<u8> = (0..query.len()).map(|i| (i * 13 % 41) as u8).collect();
+            let scored = QualityScoring {
+                qualities: &qualities,
+                penalties: Penalties::default(),
+                max_penalty: Some(8),
+            };
+            for (edits, edit_model, scoring) in (0..=3).flat_map(|edits| {
+                vec![EditModel::Levenshtein, EditModel::Hamming]
+                    .into_iter()
+                    .flat_map(move |model| {
+                        vec![None, Some(scored)]
+                            .into_iter()
+                            .map(move |scoring| (edits, model, scoring))
+                    })
+            }) {
+                let params = ApproxSearchParams {
+                    reference: &reference,
+                    query: &query,
+                    o_table: &o_table,
+                    c_table: &c_table,
+                    rev_o_table: &rev_o_table,
+                    edits,
+                    edit_model,
+                    wildcard_cost: 1,
+                    scoring,
+                };
+                let expected = approx_search(params);
+
+                let mut schemes = vec![SearchScheme::pigeonhole(edits)];
+                schemes.extend(SearchScheme::kucherov(edits));
+                for scheme in &schemes {
+                    assert_eq!(
+                        scheme_search(params, scheme),
+                        expected,
+                        "{} with {} edits, {:?}, {:?} and {:?}",
+                        read,
+                        edits,
+                        edit_model,
+                        scoring.map(|s| s.max_penalty),
+                        scheme.searches()
+                    );
+                }
+            }
+        }
+    }
+
+    /// The text positions and reference lengths of the search results.
+    fn loci(results: &Alignments, suffix_array: &[usize]) -> HashSet<(usize, usize)> {
+        results
+            .iter()
+            .flat_map(|(start, end, cigar, _)| {
+                suffix_array[*start..*end]
+                    .iter()
+                    .map(move |&position| (position, cigar.reference_span()))
+            })
+            .collect()
+    }
+
+    /// Every substring of the genome within `edits` of the query, by plain DP.
+    fn brute_force_loci(
+        reference: &[u8],
+        query: &[u8],
+        edits: usize,
+        edit_model: EditModel,
+    ) -> HashSet<(usize, usize)> {
+        let text = &reference[..reference.len() - 1];
+        (0..text.len())
+            .flat_map(|start| (1..=text.len() - start).map(move |len| (start, len)))
+            .filter(|&(start, len)| {
+                let substring = &text[start..start + len];
+                !substring.contains(&0)
+                    && edit_distance(substring, query, edit_model, 1)
+                        .is_some_and(|distance| distance <= edits)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_random_reads_match_brute_force() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..20 {
+            let genome: String = (0..60)
+                .map(|_| match rng.gen_range(0..20) {
+                    0 => 'N',
+                    i => ALPHABET[1 + i % 4],
+                })
+                .collect();
+            let reference = remap_reference(&genome).unwrap();
+            let mut reverse_reference = reference[..reference.len() - 1].to_vec();
+            reverse_reference.reverse();
+            reverse_reference.push(0);
+            let suffix_array = suffix_array_induced_sort(&reference);
+            let reverse_suffix_array = suffix_array_induced_sort(&reverse_reference);
+            let o_table = OTable::new(&reference, &suffix_array, 4);
+            let rev_o_table = OTable::new(&reverse_reference, &reverse_suffix_array, 4);
+            let c_table = generate_c_table(&reference);
+
+            for _ in 0..10 {
+                // A substring of the genome with a few random edits
+                let len = rng.gen_range(4..14);
+                let start = rng.gen_range(0..genome.len() - len);
+                let mut read: Vec<char> = genome[start..start + len].chars().collect();
+                for _ in 0..rng.gen_range(0..3) {
+                    let i = rng.gen_range(0..read.len());
+                    let base = ALPHABET[rng.gen_range(1..5)];
+                    match rng.gen_range(0..3) {
+                        0 => read[i] = base,
+                        1 => read.insert(i, base),
+                        _ => {
+                            read.remove(i);
+                        }
+                    }
+                }
+                let read: String = read.into_iter().filter(|&c| c != 'N').collect();
+                let query = remap_query(&read).unwrap();
+
+                // Deleting all of a read would match anywhere, as an empty alignment
+                for edits in (0..=2).filter(|&edits| edits < query.len()) {
+                    for edit_model in [EditModel::Levenshtein, EditModel::Hamming] {
+                        let params = ApproxSearchParams {
+                            reference: &reference,
+                            query: &query,
+                            o_table: &o_table,
+                            c_table: &c_table,
+                            rev_o_table: &rev_o_table,
+                            edits,
+                            edit_model,
+                            wildcard_cost: 1,
+                            scoring: None,
+                        };
+                        let context = format!(
+                            "{} in {} with {} edits, {:?}",
+                            read, genome, edits, edit_model
+                        );
+                        let backtracking = approx_search(params);
+                        assert_eq!(
+                            loci(&backtracking, &suffix_array),
+                            brute_force_loci(&reference, &query, edits, edit_model),
+                            "{}",
+                            context
+                        );
+
+                        let mut schemes = vec![SearchScheme::pigeonhole(edits)];
+                        schemes.extend(SearchScheme::kucherov(edits));
+                        for scheme in &schemes {
+                            assert_eq!(scheme_search(params, scheme), backtracking, "{}", context);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_schemes_visit_fewer_nodes() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let genome: String = (0..5000).map(|_| ALPHABET[rng.gen_range(1..5)]).collect();
+        let reference = remap_reference(&genome).unwrap();
+        let mut reverse_reference = reference[..reference.len() - 1].to_vec();
+        reverse_reference.reverse();
+        reverse_reference.push(0);
+        let suffix_array = suffix_array_induced_sort(&reference);
+        let reverse_suffix_array = suffix_array_induced_sort(&reverse_reference);
+        let o_table = OTable::new(&reference, &suffix_array, 10);
+        let rev_o_table = OTable::new(&reverse_reference, &reverse_suffix_array, 10);
+        let c_table = generate_c_table(&reference);
+
+        let edits = 2;
+        let mut backtracking = SearchStats::default();
+        let mut pigeonhole = SearchStats::default();
+        let mut kucherov = SearchStats::default();
+        for _ in 0..20 {
+            // Reads of the genome with two substitutions
+            let start = rng.gen_range(0..genome.len() - 50);
+            let mut read: Vec<char> = genome[start..start + 50].chars().collect();
+            for _ in 0..edits {
+                read[rng.gen_range(0..50)] = ALPHABET[rng.gen_range(1..5)];
+            }
+            let query = remap_query(&read.into_iter().collect::<String>()).unwrap();
+            let params = ApproxSearchParams {
+                reference: &reference,
+                query: &query,
+                o_table: &o_table,
+                c_table: &c_table,
+                rev_o_table: &rev_o_table,
+                edits,
+                edit_model: EditModel::Levenshtein,
+                wildcard_cost: 1,
+                scoring: None,
+            };
+
+            let (expected, stats) = approx_search_with_stats(params);
+            backtracking.merge(stats);
+            for (scheme, total) in [
+                (SearchScheme::pigeonhole(edits), &mut pigeonhole),
+                (SearchScheme::kucherov(edits).unwrap(), &mut kucherov),
+            ] {
+                let (results, stats) = scheme_search_with_stats(params, &scheme);
+                assert_eq!(results, expected);
+                total.merge(stats);
+            }
+        }
+
+        // Both find the same alignments in a fraction of the nodes
+        assert!(pigeonhole.visited * 2 < backtracking.visited);
+        assert!(kucherov.visited * 4 < backtracking.visited);
+    }
+}
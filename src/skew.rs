@@ -164,7 +164,7 @@ fn is_less(reference: &[u8], i: usize, j: usize, isa: &HashMap<usize, usize>) ->
     if a > b {
         return false;
     }
-    if i % 3 != 0 && j % 3 != 0 {
+    if !i.is_multiple_of(3) && !j.is_multiple_of(3) {
         return isa[&i] < isa[&j];
     }
     is_less(reference, i + 1, j + 1, isa)
@@ -178,14 +178,15 @@ mod tests {
     #[test]
     fn test_skew_mississippi() {
         let string = "CATTATTAGGA";
-        let reference = remap_reference(&string);
+        let reference = remap_reference(string).unwrap();
         let result = skew(&reference);
         assert_eq!(result, vec![11, 10, 7, 4, 1, 0, 9, 8, 6, 3, 5, 2]);
     }
     #[test]
     fn test_skew_medium_string() {
         let reference =
-            remap_reference("AATAAACCTTACCTAGCACTCCATCATGTCTTATGGCGCGTGATTTGCCCCGGACTCAGG");
+            remap_reference("AATAAACCTTACCTAGCACTCCATCATGTCTTATGGCGCGTGATTTGCCCCGGACTCAGG")
+                .unwrap();
         let result = skew(&reference);
         assert_eq!(
             result,
@@ -198,10 +199,10 @@ mod tests {
     }
     #[test]
     fn test_skew_aaaaaa() {
-        let reference = remap_reference("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA");
+        let reference = remap_reference("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA").unwrap();
         let result = skew(&reference);
-        for i in 0..reference.len() {
-            assert_eq!(result[i], reference.len() - i - 1);
+        for (i, &suffix) in result.iter().enumerate() {
+            assert_eq!(suffix, reference.len() - i - 1);
         }
     }
 }
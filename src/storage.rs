@@ -0,0 +1,234 @@
+use memmap2::Mmap;
+use std::convert::TryInto;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+/// Element types a table can hold.
+///
+/// # Safety
+/// Implementors must be valid for every bit pattern, so that a correctly sized and
+/// aligned slice of a memory map can be read as a slice of them.
+pub unsafe trait Plain: Copy + 'static {
+    /// Whether the bytes of an index file have the layout of this type on the
+    /// current target, so a mapped section can be used as is.
+    const MAPPABLE: bool;
+
+    /// Reads the little-endian bytes of an index file section.
+    fn decode(bytes: &[u8]) -> Vec<Self>;
+}
+
+unsafe impl Plain for u8 {
+    const MAPPABLE: bool = true;
+
+    fn decode(bytes: &[u8]) -> Vec<Self> {
+        bytes.to_vec()
+    }
+}
+
+unsafe impl Plain for u64 {
+    const MAPPABLE: bool = cfg!(target_endian = "little");
+
+    fn decode(bytes: &[u8]) -> Vec<Self> {
+        bytes
+            .chunks_exact(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect()
+    }
+}
+
+// Index files store every integer as a little-endian u64
+unsafe impl Plain for usize {
+    const MAPPABLE: bool = cfg!(all(target_pointer_width = "64", target_endian = "little"));
+
+    fn decode(bytes: &[u8]) -> Vec<Self> {
+        bytes
+            .chunks_exact(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()) as usize)
+            .collect()
+    }
+}
+
+/// Eight words aligned to a cache line, so that every element of a table of them
+/// fills exactly one line.
+#[repr(C, align(64))]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CacheLine(pub [u64; 8]);
+
+impl CacheLine {
+    /// The words of consecutive lines.
+    pub fn words(lines: &[CacheLine]) -> &[u64] {
+        // Safety: a line is eight u64s without padding, so the lines are a valid,
+        // sufficiently aligned slice of eight times as many words
+        unsafe { std::slice::from_raw_parts(lines.as_ptr() as *const u64, lines.len() * 8) }
+    }
+
+    /// The words of consecutive lines, for filling them.
+    pub fn words_mut(lines: &mut [CacheLine]) -> &mut [u64] {
+        // Safety: as in `words`, and the borrow of the lines is held
+        unsafe { std::slice::from_raw_parts_mut(lines.as_mut_ptr() as *mut u64, lines.len() * 8) }
+    }
+}
+
+unsafe impl Plain for CacheLine {
+    const MAPPABLE: bool = u64::MAPPABLE;
+
+    fn decode(bytes: &[u8]) -> Vec<Self> {
+        bytes
+            .chunks_exact(64)
+            .map(|chunk| CacheLine(u64::decode(chunk).try_into().unwrap()))
+            .collect()
+    }
+}
+
+/// A table that is either owned or a section of a memory-mapped index file.
+/// Mapped tables are shared through the page cache by every process that maps
+/// the same file, and are copied onto the heap only if they are modified.
+pub enum Storage<T> {
+    Owned(Vec<T>),
+    Mapped {
+        map: Arc<Mmap>,
+        /// Byte offset of the first element in the map
+        offset: usize,
+        len: usize,
+    },
+}
+
+impl<T: Plain> Storage<T> {
+    /// Uses `len` elements starting at byte `offset` of `map` without copying them,
+    /// or decodes them onto the heap if the target cannot read them in place.
+    pub fn mapped(map: &Arc<Mmap>, offset: usize, len: usize) -> Self {
+        let size = std::mem::size_of::<T>();
+        let bytes = &map[offset..offset + len * size];
+        if T::MAPPABLE && (bytes.as_ptr() as *const T).is_aligned() {
+            Storage::Mapped {
+                map: Arc::clone(map),
+                offset,
+                len,
+            }
+        } else {
+            Storage::Owned(T::decode(bytes))
+        }
+    }
+
+    pub fn is_mapped(&self) -> bool {
+        matches!(self, Storage::Mapped { .. })
+    }
+
+    /// Copies a mapped table onto the heap.
+    pub fn into_owned(self) -> Vec<T> {
+        match self {
+            Storage::Owned(values) => values,
+            mapped => mapped.to_vec(),
+        }
+    }
+}
+
+impl<T: Plain> Deref for Storage<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        match self {
+            Storage::Owned(values) => values,
+            Storage::Mapped { map, offset, len } => {
+                let ptr = map[*offset..].as_ptr() as *const T;
+                // Safety: `mapped` checked that the range lies within the map and is
+                // aligned for T, and `Plain` guarantees every bit pattern is valid.
+                // The map is kept alive by the Arc for as long as the slice.
+                unsafe { std::slice::from_raw_parts(ptr, *len) }
+            }
+        }
+    }
+}
+
+impl<T: Plain> DerefMut for Storage<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        if self.is_mapped() {
+            *self = Storage::Owned(self.to_vec());
+        }
+        match self {
+            Storage::Owned(values) => values,
+            Storage::Mapped { .. } => unreachable!(),
+        }
+    }
+}
+
+impl<T: Plain> Clone for Storage<T> {
+    fn clone(&self) -> Self {
+        match self {
+            Storage::Owned(values) => Storage::Owned(values.clone()),
+            Storage::Mapped { map, offset, len } => Storage::Mapped {
+                map: Arc::clone(map),
+                offset: *offset,
+                len: *len,
+            },
+        }
+    }
+}
+
+impl<T: Plain + fmt::Debug> fmt::Debug for Storage<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+// Tables compare by their elements, wherever they are stored
+impl<T: Plain + PartialEq> PartialEq for Storage<T> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: Plain + Eq> Eq for Storage<T> {}
+
+impl<T> From<Vec<T>> for Storage<T> {
+    fn from(values: Vec<T>) -> Self {
+        Storage::Owned(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::Write;
+
+    fn map_bytes(bytes: &[u8], name: &str) -> Arc<Mmap> {
+        let path = std::env::temp_dir().join(name);
+        File::create(&path).unwrap().write_all(bytes).unwrap();
+        let file = File::open(&path).unwrap();
+        let map = unsafe { Mmap::map(&file) }.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        Arc::new(map)
+    }
+
+    #[test]
+    fn test_mapped_usizes() {
+        let mut bytes = vec![0xff; 8];
+        for value in [3u64, 1, 4] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        let map = map_bytes(&bytes, "gene_search_test_mapped_usizes");
+
+        let table: Storage<usize> = Storage::mapped(&map, 8, 3);
+        assert_eq!(&*table, &[3, 1, 4]);
+        assert_eq!(table.is_mapped(), usize::MAPPABLE);
+
+        // Unaligned sections are decoded onto the heap
+        let unaligned: Storage<usize> = Storage::mapped(&map, 7, 1);
+        assert!(!unaligned.is_mapped());
+        assert_eq!(unaligned[0], 0x03ff);
+    }
+
+    #[test]
+    fn test_modifying_copies() {
+        let map = map_bytes(&[1, 2, 3, 4], "gene_search_test_modifying_copies");
+        let mut table: Storage<u8> = Storage::mapped(&map, 1, 2);
+        assert!(table.is_mapped());
+
+        table[0] = 9;
+        assert!(!table.is_mapped());
+        assert_eq!(&*table, &[9, 3]);
+        assert_eq!(map[1], 2);
+    }
+}
@@ -8,7 +8,7 @@ fn generate_o_table_naive(reference: &[u8], suffix_array: &[usize]) -> Vec<Vec<u
     let mut new_bwt_output = Vec::new();
     for i in 0..suffix_array.len() {
         let mut new_rank_table = old_rank_table.clone();
-        let j = bwt(reference, &suffix_array, i);
+        let j = bwt(reference, suffix_array, i);
         new_rank_table[usize::from(j)] += 1;
         old_rank_table = new_rank_table.clone();
         new_bwt_output.push(new_rank_table.clone());
@@ -18,7 +18,7 @@ fn generate_o_table_naive(reference: &[u8], suffix_array: &[usize]) -> Vec<Vec<u
 }
 
 pub fn generate_c_table(reference: &[u8]) -> Vec<usize> {
-    let mut counts = vec![0; 5];
+    let mut counts = [0; 5];
     for &c in reference {
         counts[usize::from(c)] += 1;
     }
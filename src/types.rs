@@ -1,5 +1,17 @@
+use crate::cigar::Cigar;
+use crate::util::Strand;
+use std::collections::HashSet;
+
 pub type SuffixArray = Vec<usize>;
 
 pub type CTable = Vec<usize>;
 
 pub type DTable = Vec<usize>;
+
+/// Approximate matches of a read as `(left, right, cigar, edits)`, with half-open
+/// SA intervals.
+pub type Alignments = HashSet<(usize, usize, Cigar, usize)>;
+
+/// Approximate matches of a read as `(strand, left, right, cigar, edits)`, with
+/// half-open SA intervals.
+pub type StrandAlignments = HashSet<(Strand, usize, usize, Cigar, usize)>;
@@ -1,27 +1,169 @@
 #![allow(dead_code)]
 
-use crate::o_table::OTable;
-use crate::sais::{find_bucket_heads, find_bucket_tails, suffix_array_induced_sort};
-use crate::types::SuffixArray;
+use crate::error::{with_path, GeneSearchError, Result};
+use crate::input::{is_stdin, open_input_or_stdin, Input};
+use crate::quality::phred_qualities;
+use crate::reference::{Reference, RECORD_SEPARATOR};
+use crate::sais::{find_bucket_heads, find_bucket_tails};
 use crate::ALPHABET;
 use num::{NumCast, Unsigned};
-use seq_io::fastq::Reader;
-use std::io::{BufReader, Read, Write};
-use std::path::Path;
-use std::{fs::File, io::Result};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use seq_io::fastq::{Reader, Record};
+use std::io::Read;
+use std::path::{Path, PathBuf};
 
-pub fn remap_reference<T: Unsigned + NumCast>(s: &str) -> Vec<T> {
-    let mut remapped = remap_query(s);
-    remapped.push(T::zero());
-    remapped
+/// Symbol used in remapped queries for N and the other IUPAC ambiguity codes.
+/// It lies outside the alphabet, so it never occurs in an index.
+pub const WILDCARD: u8 = ALPHABET.len() as u8;
+
+const AMBIGUITY_CODES: &str = "NRYKMSWBDHV";
+
+/// How N and the other IUPAC ambiguity codes in a reference are remapped.
+/// Both policies keep one symbol per input character, so coordinates are preserved.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AmbiguityPolicy {
+    /// Keep them as the non-matching `$` symbol, so no read can align across them.
+    NonMatching,
+    /// Replace them with random bases and record the masked intervals.
+    Randomize,
+}
+
+pub struct RemappedReference<T> {
+    pub sequence: Vec<T>,
+    /// Half-open intervals of the reference that were replaced by random bases
+    pub masked: Vec<(usize, usize)>,
 }
 
-pub fn remap_query<T: Unsigned + NumCast>(s: &str) -> Vec<T> {
+enum Symbol {
+    Base(usize),
+    Ambiguous,
+}
+
+/// Classifies the character at `position` of a sequence, failing on characters
+/// that are neither bases, separators nor ambiguity codes.
+fn classify(c: char, position: usize) -> Result<Symbol> {
+    let upper = c.to_ascii_uppercase();
+    match ALPHABET.iter().position(|&a| a == upper) {
+        Some(i) => Ok(Symbol::Base(i)),
+        None if AMBIGUITY_CODES.contains(upper) => Ok(Symbol::Ambiguous),
+        None => Err(GeneSearchError::Parse(format!(
+            "could not remap character {:?} at position {}",
+            c, position
+        ))),
+    }
+}
+
+/// Whether a character is a base or an ambiguity code, in either case. The
+/// separator is not one: neither FASTA input nor reads may contain it.
+pub fn is_known_symbol(c: char) -> bool {
+    let c = c.to_ascii_uppercase();
+    c != RECORD_SEPARATOR && (ALPHABET.contains(&c) || AMBIGUITY_CODES.contains(c))
+}
+
+fn cast<T: NumCast>(c: usize) -> T {
+    match num::cast(c) {
+        Some(v) => v,
+        None => panic!("could not remap character {}", c),
+    }
+}
+
+pub fn remap_reference<T: Unsigned + NumCast>(s: &str) -> Result<Vec<T>> {
+    Ok(remap_reference_with_policy(s, AmbiguityPolicy::NonMatching)?.sequence)
+}
+
+/// Remaps a reference and appends the sentinel. Lowercase (soft-masked) bases are
+/// treated as uppercase and ambiguity codes are handled according to `policy`.
+/// Fails on any other character.
+pub fn remap_reference_with_policy<T: Unsigned + NumCast>(
+    s: &str,
+    policy: AmbiguityPolicy,
+) -> Result<RemappedReference<T>> {
+    // Seeded, so the same genome always gives the same index
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut sequence = Vec::with_capacity(s.len() + 1);
+    let mut masked: Vec<(usize, usize)> = Vec::new();
+
+    for (i, c) in s.chars().enumerate() {
+        let symbol = match (classify(c, i)?, policy) {
+            (Symbol::Base(a), _) => a,
+            (Symbol::Ambiguous, AmbiguityPolicy::NonMatching) => 0,
+            (Symbol::Ambiguous, AmbiguityPolicy::Randomize) => {
+                match masked.last_mut() {
+                    Some((_, end)) if *end == i => *end += 1,
+                    _ => masked.push((i, i + 1)),
+                }
+                rng.gen_range(1..ALPHABET.len())
+            }
+        };
+        sequence.push(cast(symbol));
+    }
+    sequence.push(T::zero());
+
+    Ok(RemappedReference { sequence, masked })
+}
+
+/// Remaps a query. Lowercase bases are treated as uppercase and ambiguity codes
+/// become `WILDCARD`. Fails on any other character, `RECORD_SEPARATOR` included,
+/// as a query containing it could match across records.
+pub fn remap_query<T: Unsigned + NumCast>(s: &str) -> Result<Vec<T>> {
     s.chars()
-        .flat_map(|c| ALPHABET.iter().position(|a| a == &c))
-        .map(|c| match num::cast(c) {
-            Some(v) => v,
-            None => panic!("could not remap character {}", c),
+        .enumerate()
+        .map(|(i, c)| match classify(c, i)? {
+            Symbol::Base(0) => Err(GeneSearchError::Parse(format!(
+                "could not remap character {:?} at position {}, the record separator cannot be searched for",
+                c, i
+            ))),
+            Symbol::Base(a) => Ok(cast(a)),
+            Symbol::Ambiguous => Ok(cast(WILDCARD as usize)),
+        })
+        .collect()
+}
+
+/// The strand of the reference a read was found on.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Strand {
+    Forward,
+    Reverse,
+}
+
+/// Reverse complements a remapped query. Wildcards and separators are kept as is.
+pub fn reverse_complement(query: &[u8]) -> Vec<u8> {
+    query
+        .iter()
+        .rev()
+        .map(|&c| match c {
+            1..=4 => 5 - c,
+            _ => c,
+        })
+        .collect()
+}
+
+/// Reverse complements a sequence of bases, keeping the case of each base.
+/// IUPAC ambiguity codes are complemented as well.
+pub fn reverse_complement_text(s: &str) -> String {
+    s.chars()
+        .rev()
+        .map(|c| {
+            let complement = match c.to_ascii_uppercase() {
+                'A' => 'T',
+                'C' => 'G',
+                'G' => 'C',
+                'T' => 'A',
+                'R' => 'Y',
+                'Y' => 'R',
+                'K' => 'M',
+                'M' => 'K',
+                'B' => 'V',
+                'V' => 'B',
+                'D' => 'H',
+                'H' => 'D',
+                other => other,
+            };
+            if c.is_ascii_lowercase() {
+                complement.to_ascii_lowercase()
+            } else {
+                complement
+            }
         })
         .collect()
 }
@@ -57,8 +199,8 @@ pub fn bwm(x: &[u8], i: usize) -> String {
 }
 
 pub fn print_sais_buckets(suffix_array: &[usize], bucket_sizes: &[usize], i: usize) {
-    let bucket_heads = find_bucket_heads(&bucket_sizes);
-    let bucket_tails = find_bucket_tails(&bucket_sizes);
+    let bucket_heads = find_bucket_heads(bucket_sizes);
+    let bucket_tails = find_bucket_tails(bucket_sizes);
 
     for i in 0..bucket_sizes.len() {
         let start = bucket_heads[i];
@@ -86,90 +228,266 @@ pub fn print_sais_buckets(suffix_array: &[usize], bucket_sizes: &[usize], i: usi
     println!();
 }
 
-pub fn try_read_genome(file_name: &str) -> Result<String> {
-    let mut path = Path::new("resources/genomes/").join(file_name);
-    path.set_extension("fa");
-    let genome_file = File::open(path)?;
-    let mut buf_reader = BufReader::new(genome_file);
-    let mut genome_string_raw = String::new();
-    buf_reader.read_to_string(&mut genome_string_raw)?;
-    let mut genome_string = genome_string_raw.replace('\n', "");
-    genome_string = genome_string.replace("> chr1", "");
-
-    Ok(genome_string)
+pub fn read_reference(file_name: &str) -> Result<Reference> {
+    Reference::from_file(file_name)
 }
 
-pub fn read_genome(file_name: &str) -> String {
-    match try_read_genome(file_name) {
-        Ok(genome) => genome,
-        Err(e) => panic!("Could not read genome: {}", e),
-    }
+/// Reads all records of a genome, concatenated with `RECORD_SEPARATOR` between them.
+pub fn read_genome(file_name: &str) -> Result<String> {
+    Ok(read_reference(file_name)?.text()?.to_string())
 }
 
-pub fn read_and_remap_genome<T>(file_name: &str) -> Vec<T>
+pub fn read_and_remap_genome<T>(file_name: &str) -> Result<Vec<T>>
 where
     T: Unsigned + NumCast,
 {
-    let genome = match try_read_genome(file_name) {
-        Ok(genome) => genome,
-        Err(_) => panic!("could not read genome"),
-    };
+    remap_reference(&read_genome(file_name)?)
+}
 
-    remap_reference(&genome)
+/// A FASTQ record with its name and qualities kept as text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadRecord {
+    pub name: String,
+    pub sequence: String,
+    pub quality: String,
 }
 
-pub fn read_and_remap_reads(file_name: &str) -> Result<Vec<Vec<u8>>> {
+pub fn read_reads(file_name: &str) -> Result<Vec<ReadRecord>> {
     // Get full file path
     let mut path = Path::new("resources/reads/").join(file_name);
     path.set_extension("fq");
 
-    // Parse file
-    let reads_file = File::open(path)?;
-    let file_reader = BufReader::new(reads_file);
-    let mut parser = Reader::new(file_reader);
-
-    Ok(parser
-        .records()
-        .map(|result| result.unwrap())
-        .map(|record| record.seq)
-        .map(|seq| {
-            let mut s = String::new();
-            seq.iter().for_each(|&c| s.push(c as char));
-            remap_query(&s)
-        })
-        .collect())
+    read_reads_from_path(&path)
+}
+
+/// Reads every record of a FASTQ file at any path. Fails on malformed records and
+/// on reads with characters that are not bases or IUPAC ambiguity codes.
+pub fn read_reads_from_path(path: &Path) -> Result<Vec<ReadRecord>> {
+    ReadSource::from_path(path)?.collect()
 }
 
-pub fn get_sa(file_name: &str, genome: &[u8], rev: bool) -> SuffixArray {
-    let mut file_name = String::from(file_name);
-    if rev {
-        file_name.push_str("_rev");
+/// Reads the records of a FASTQ file one at a time, so files of any size can be
+/// processed in constant memory. Fails on the same records as `read_reads_from_path`.
+pub struct ReadSource<R: Read> {
+    parser: Reader<R>,
+    /// Added to the errors of records read from a file
+    path: Option<PathBuf>,
+}
+
+impl ReadSource<Input> {
+    /// Reads a FASTQ file, which may be gzip-compressed, or standard input if the
+    /// path is `-`.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        Ok(ReadSource {
+            parser: Reader::new(open_input_or_stdin(path)?),
+            path: Some(path.to_path_buf()).filter(|path| !is_stdin(path)),
+        })
     }
+}
 
-    let sa_path = Path::new("resources/sa/").join(file_name);
-    match File::open(&sa_path) {
-        Ok(f) => {
-            let buf_reader = BufReader::new(f);
-            let decoded: SuffixArray = bincode::deserialize_from(buf_reader).unwrap();
-            decoded
+impl<R: Read> ReadSource<R> {
+    pub fn new(reader: R) -> Self {
+        ReadSource {
+            parser: Reader::new(reader),
+            path: None,
         }
-        Err(_) => {
-            let sa = suffix_array_induced_sort(&genome);
-            let bytes: Vec<u8> = bincode::serialize(&sa).unwrap();
-            let mut file = File::create(&sa_path).unwrap();
-            file.write_all(&bytes).unwrap();
-            sa
+    }
+
+    /// Reads up to `size` records, returning fewer only at the end of the file.
+    pub fn next_batch(&mut self, size: usize) -> Result<Vec<ReadRecord>> {
+        self.take(size).collect()
+    }
+
+    /// Splits the remaining records into batches of `size`.
+    pub fn batches(mut self, size: usize) -> impl Iterator<Item = Result<Vec<ReadRecord>>> {
+        std::iter::from_fn(move || match self.next_batch(size) {
+            Ok(batch) if batch.is_empty() => None,
+            result => Some(result),
+        })
+    }
+
+    fn next_record(&mut self) -> Option<Result<ReadRecord>> {
+        let record = match self.parser.next()? {
+            Ok(record) => record,
+            Err(e) => return Some(Err(e.into())),
+        };
+        let read = ReadRecord {
+            name: String::from_utf8_lossy(record.head())
+                .split_whitespace()
+                .next()
+                .unwrap_or("")
+                .to_string(),
+            sequence: String::from_utf8_lossy(record.seq()).into_owned(),
+            quality: String::from_utf8_lossy(record.qual()).into_owned(),
+        };
+
+        if let Some(c) = read.sequence.chars().find(|&c| !is_known_symbol(c)) {
+            return Some(Err(GeneSearchError::Parse(format!(
+                "unexpected character {:?} in read {}",
+                c, read.name
+            ))));
         }
+        Some(Ok(read))
     }
 }
 
-pub fn get_o_table<'a>(
-    file_name: &str,
-    genome: &'a [u8],
-    suffix_array: &'a [usize],
-    spacing: usize,
-    rev: bool,
-) -> OTable<'a> {
-    let file_name = &format!("{}{}", file_name, if rev { "_rev" } else { "" });
-    OTable::from_file(file_name, genome, suffix_array, spacing)
+impl<R: Read> Iterator for ReadSource<R> {
+    type Item = Result<ReadRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = self.next_record()?;
+        Some(match &self.path {
+            Some(path) => with_path(record, path),
+            None => record,
+        })
+    }
+}
+
+pub fn read_and_remap_reads(file_name: &str) -> Result<Vec<Vec<u8>>> {
+    let mut path = Path::new("resources/reads/").join(file_name);
+    path.set_extension("fq");
+
+    ReadSource::from_path(&path)?
+        .map(|read| remap_query(&read?.sequence))
+        .collect()
+}
+
+/// Like `read_and_remap_reads`, but keeps the Phred qualities of every read.
+pub fn read_and_remap_reads_with_qualities(file_name: &str) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut path = Path::new("resources/reads/").join(file_name);
+    path.set_extension("fq");
+
+    ReadSource::from_path(&path)?
+        .map(|read| {
+            let read = read?;
+            Ok((remap_query(&read.sequence)?, phred_qualities(&read.quality)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        exact_search::bwt_search,
+        locate::locate_exact,
+        o_table::OTable,
+        sais::suffix_array_induced_sort,
+        sampled_suffix_array::{SampledSuffixArray, SamplingStrategy},
+        table_gen::generate_c_table,
+    };
+
+    #[test]
+    fn test_remap_keeps_coordinates() {
+        let remapped: Vec<u8> = remap_reference("acgtNNAryT").unwrap();
+        assert_eq!(remapped, vec![1, 2, 3, 4, 0, 0, 1, 0, 0, 4, 0]);
+    }
+
+    #[test]
+    fn test_locate_next_to_n_runs() {
+        let reference: Vec<u8> = remap_reference("ACGTNNNNGATTACANNTTGCANACGN").unwrap();
+        let suffix_array = suffix_array_induced_sort(&reference);
+        let o_table = OTable::new(&reference, &suffix_array, 4);
+        let c_table = generate_c_table(&reference);
+
+        for &strategy in &[SamplingStrategy::TextPosition, SamplingStrategy::Rank] {
+            for rate in 1..12 {
+                let sampled = SampledSuffixArray::new(&reference, &suffix_array, rate, strategy);
+                let lookup = sampled.with_tables(&o_table, &c_table);
+                for (read, expected) in [
+                    ("ACG", vec![0, 23]),
+                    ("GATTACA", vec![8]),
+                    ("TTGCA", vec![17]),
+                    ("CA", vec![13, 20]),
+                ] {
+                    let interval = bwt_search(&remap_query(read).unwrap(), &o_table, &c_table);
+                    let mut positions = locate_exact(&lookup, interval, None);
+                    positions.sort_unstable();
+                    assert_eq!(positions, expected, "{} with rate {}", read, rate);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_remap_randomize() {
+        let remapped: RemappedReference<u8> =
+            remap_reference_with_policy("ACNNNGTNA", AmbiguityPolicy::Randomize).unwrap();
+
+        assert_eq!(remapped.sequence.len(), 10);
+        assert_eq!(remapped.masked, vec![(2, 5), (7, 8)]);
+        assert_eq!(&remapped.sequence[..2], &[1, 2]);
+        assert_eq!(&remapped.sequence[5..7], &[3, 4]);
+        assert!(remapped.sequence[2..5]
+            .iter()
+            .all(|&c| (1..=4).contains(&c)));
+    }
+
+    #[test]
+    fn test_reverse_complement() {
+        let query: Vec<u8> = remap_query("AACGN").unwrap();
+        assert_eq!(
+            reverse_complement(&query),
+            remap_query::<u8>("NCGTT").unwrap()
+        );
+        assert_eq!(reverse_complement_text("AACGNr"), "yNCGTT");
+    }
+
+    #[test]
+    fn test_remap_rejects_unknown_characters() {
+        let error = remap_query::<u8>("ACxGT").unwrap_err();
+        assert!(matches!(error, GeneSearchError::Parse(_)));
+        assert!(error.to_string().contains("'x' at position 2"));
+
+        let error = remap_reference::<u8>("ACGT-A").unwrap_err();
+        assert!(error.to_string().contains("'-' at position 4"));
+
+        // Separators are only remapped in references
+        let error = remap_query::<u8>("GT$TT").unwrap_err();
+        assert!(error.to_string().contains("'$' at position 2"));
+        assert_eq!(
+            remap_reference::<u8>("GT$TT").unwrap(),
+            vec![3, 4, 0, 4, 4, 0]
+        );
+    }
+
+    #[test]
+    fn test_remap_query_wildcard() {
+        let remapped: Vec<u8> = remap_query("AnGT").unwrap();
+        assert_eq!(remapped, vec![1, WILDCARD, 3, 4]);
+    }
+
+    #[test]
+    fn test_read_source_batches() {
+        let fastq = "@r1 first\nACGT\n+\nIIII\n@r2\nGGN\n+\nII#\n@r3\nT\n+\nI\n";
+        let batches: Vec<Vec<ReadRecord>> = ReadSource::new(fastq.as_bytes())
+            .batches(2)
+            .collect::<Result<_>>()
+            .unwrap();
+
+        assert_eq!(batches.len(), 2);
+        assert_eq!(
+            batches[0][0],
+            ReadRecord {
+                name: "r1".to_string(),
+                sequence: "ACGT".to_string(),
+                quality: "IIII".to_string(),
+            }
+        );
+        assert_eq!(batches[0][1].quality, "II#");
+        assert_eq!(batches[1][0].name, "r3");
+    }
+
+    #[test]
+    fn test_read_source_rejects_unknown_characters() {
+        let fastq = "@r1\nACGT\n+\nIIII\n@r2\nAC.T\n+\nIIII\n@r3\nAC$T\n+\nIIII\n";
+        let mut source = ReadSource::new(fastq.as_bytes());
+
+        assert!(source.next().unwrap().is_ok());
+        let error = source.next().unwrap().unwrap_err();
+        assert!(matches!(error, GeneSearchError::Parse(_)));
+        assert!(error.to_string().contains("r2"));
+        let error = source.next().unwrap().unwrap_err();
+        assert!(error.to_string().contains("r3"));
+    }
 }
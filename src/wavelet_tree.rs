@@ -0,0 +1,207 @@
+use crate::rank::Rank;
+use crate::util::bwt;
+use crate::ALPHABET;
+
+/// Words covered by each stored count of a `RankBitVector`.
+const SUPERBLOCK_WORDS: usize = 8;
+
+/// A bit vector with the number of set bits before every superblock of 512 bits,
+/// so rank takes at most eight popcounts.
+struct RankBitVector {
+    words: Vec<u64>,
+    superblocks: Vec<usize>,
+}
+
+impl RankBitVector {
+    fn new(bits: &[bool]) -> Self {
+        let mut words = vec![0u64; bits.len() / 64 + 1];
+        for (i, _) in bits.iter().enumerate().filter(|(_, &bit)| bit) {
+            words[i / 64] |= 1 << (i % 64);
+        }
+
+        let mut superblocks = Vec::with_capacity(words.len() / SUPERBLOCK_WORDS + 1);
+        let mut ones = 0;
+        for block in words.chunks(SUPERBLOCK_WORDS) {
+            superblocks.push(ones);
+            ones += block.iter().map(|w| w.count_ones() as usize).sum::<usize>();
+        }
+
+        RankBitVector { words, superblocks }
+    }
+
+    fn get(&self, i: usize) -> bool {
+        self.words[i / 64] >> (i % 64) & 1 == 1
+    }
+
+    /// Counts the set bits before position `i`.
+    fn rank1(&self, i: usize) -> usize {
+        let word = i / 64;
+        let superblock = word / SUPERBLOCK_WORDS;
+        let whole: usize = self.words[superblock * SUPERBLOCK_WORDS..word]
+            .iter()
+            .map(|w| w.count_ones() as usize)
+            .sum();
+        let partial = self.words[word] & ((1 << (i % 64)) - 1);
+        self.superblocks[superblock] + whole + partial.count_ones() as usize
+    }
+
+    fn rank(&self, bit: bool, i: usize) -> usize {
+        if bit {
+            self.rank1(i)
+        } else {
+            i - self.rank1(i)
+        }
+    }
+
+    fn size_in_bytes(&self) -> usize {
+        std::mem::size_of_val(&self.words[..]) + std::mem::size_of_val(&self.superblocks[..])
+    }
+}
+
+/// A node splitting the symbols `lo..hi` into `lo..mid` and `mid..hi`, with a
+/// bit per position telling which half its symbol is in.
+struct Node {
+    bits: RankBitVector,
+    /// Nodes for the halves with more than one symbol
+    children: [Option<usize>; 2],
+}
+
+/// A balanced wavelet tree over the BWT, answering rank in one bit vector rank
+/// per level. It stores no sampled counts, so it takes about 3 bits per position
+/// whatever the spacing of the O-table it is compared with.
+pub struct WaveletTree {
+    len: usize,
+    /// The root is the first node
+    nodes: Vec<Node>,
+}
+
+impl WaveletTree {
+    /// Builds the tree of a BWT over the remapped alphabet.
+    pub fn new(bwt: &[u8]) -> Self {
+        let mut tree = WaveletTree {
+            len: bwt.len(),
+            nodes: Vec::new(),
+        };
+        tree.add_node(bwt, 0, ALPHABET.len() as u8);
+        tree
+    }
+
+    /// Builds the tree of the BWT of a string from its suffix array.
+    pub fn from_suffix_array(string: &[u8], suffix_array: &[usize]) -> Self {
+        let bwt: Vec<u8> = (0..suffix_array.len())
+            .map(|i| bwt(string, suffix_array, i))
+            .collect();
+        WaveletTree::new(&bwt)
+    }
+
+    /// Builds a bit vector of a node for the symbols `lo..hi`, then the nodes
+    /// of its halves, returning its index.
+    fn add_node(&mut self, symbols: &[u8], lo: u8, hi: u8) -> usize {
+        let mid = lo + (hi - lo) / 2;
+        let bits: Vec<bool> = symbols.iter().map(|&c| c >= mid).collect();
+        let index = self.nodes.len();
+        self.nodes.push(Node {
+            bits: RankBitVector::new(&bits),
+            children: [None, None],
+        });
+
+        if mid - lo > 1 {
+            let left: Vec<u8> = symbols.iter().copied().filter(|&c| c < mid).collect();
+            self.nodes[index].children[0] = Some(self.add_node(&left, lo, mid));
+        }
+        if hi - mid > 1 {
+            let right: Vec<u8> = symbols.iter().copied().filter(|&c| c >= mid).collect();
+            self.nodes[index].children[1] = Some(self.add_node(&right, mid, hi));
+        }
+        index
+    }
+}
+
+impl Rank for WaveletTree {
+    fn rank(&self, a: u8, mut i: usize) -> usize {
+        let (mut lo, mut hi) = (0, ALPHABET.len() as u8);
+        let mut node = &self.nodes[0];
+        loop {
+            let mid = lo + (hi - lo) / 2;
+            let bit = a >= mid;
+            i = node.bits.rank(bit, i);
+            match node.children[bit as usize] {
+                Some(child) => node = &self.nodes[child],
+                None => return i,
+            }
+            if bit {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+    }
+
+    fn symbol(&self, mut i: usize) -> u8 {
+        let (mut lo, mut hi) = (0, ALPHABET.len() as u8);
+        let mut node = &self.nodes[0];
+        loop {
+            let mid = lo + (hi - lo) / 2;
+            let bit = node.bits.get(i);
+            i = node.bits.rank(bit, i);
+            if bit {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+            match node.children[bit as usize] {
+                Some(child) => node = &self.nodes[child],
+                None => return lo,
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn size_in_bytes(&self) -> usize {
+        self.nodes
+            .iter()
+            .map(|node| node.bits.size_in_bytes())
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::o_table::OTable;
+    use crate::sais::suffix_array_induced_sort;
+    use crate::util::remap_reference;
+
+    #[test]
+    fn test_matches_o_table() {
+        let reference =
+            remap_reference("ACGTNNATCGTGACGGGCTATAGCGANNNTGTCGATGCAATTGGCCATGCAGTNACGT").unwrap();
+        let sa = suffix_array_induced_sort(&reference);
+        let o_table = OTable::new(&reference, &sa, 4);
+        let tree = WaveletTree::from_suffix_array(&reference, &sa);
+
+        assert_eq!(tree.len(), Rank::len(&o_table));
+        for i in 0..tree.len() {
+            assert_eq!(tree.symbol(i), o_table.bwt_symbol(i));
+        }
+        for a in 0..ALPHABET.len() as u8 {
+            for i in 0..=tree.len() {
+                assert_eq!(tree.rank(a, i), o_table.get(a, i));
+            }
+        }
+    }
+
+    #[test]
+    fn test_rank_across_superblocks() {
+        let bits: Vec<bool> = (0..2000).map(|i| i % 3 == 0 || i % 7 == 0).collect();
+        let vector = RankBitVector::new(&bits);
+
+        for &i in &[0, 1, 63, 64, 511, 512, 513, 1999, 2000] {
+            let expected = bits[..i].iter().filter(|&&bit| bit).count();
+            assert_eq!(vector.rank1(i), expected);
+        }
+    }
+}